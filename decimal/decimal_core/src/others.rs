@@ -73,7 +73,9 @@ pub fn generate_others(characteristics: DecimalCharacteristics) -> proc_macro::T
             fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
                 if Self::scale() > 0 {
                     let mut decimal_places = self.get().checked_rem(Self::one()).unwrap();
-                    let mut non_zero_tail = 0;
+                    // a zero remainder is still printed as one digit
+                    let mut non_zero_tail = 1;
+                    decimal_places /= 10;
 
                     while decimal_places > 0 {
                         non_zero_tail += 1;
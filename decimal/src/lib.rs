@@ -64,6 +64,15 @@ pub mod tests {
         assert_eq!(N::from_decimal_up(q), N(15));
     }
 
+    #[test]
+    fn test_display() {
+        assert_eq!(R(0).to_string(), "0.000");
+        assert_eq!(R(42).to_string(), "0.042");
+        assert_eq!(R(3000).to_string(), "3.000");
+        assert_eq!(Q { v: 144 }.to_string(), "14.4");
+        assert_eq!(N(3).to_string(), "3");
+    }
+
     #[test]
     fn test_ops() {
         assert_eq!(N(0) + N(0), N::new(0));
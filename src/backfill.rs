@@ -0,0 +1,219 @@
+use std::future::Future;
+
+use futures::stream::{self, StreamExt};
+use solana_client::rpc_response::RpcConfirmedTransactionStatusWithSignature;
+
+use crate::structs::{BotMsg, ObV2Gap};
+
+/// Upper bound of transactions replayed after a disconnect
+pub const BACKFILL_MAX_SIGNATURES: usize = 5000;
+
+/// Transactions fetched at once during a backfill
+pub const BACKFILL_CONCURRENCY: usize = 16;
+
+/// Transactions a parser replayed after a reconnect
+#[derive(Debug, Default)]
+pub struct Backfill {
+    /// (slot, signature, output), oldest first
+    pub msgs: Vec<(u64, String, BotMsg)>,
+    /// Transactions that couldn't be fetched or parsed, skipped
+    pub errors: Vec<anyhow::Error>,
+    /// Slots left out, when the signature cap or a failed page cut the window short
+    pub gap: Option<ObV2Gap>,
+}
+
+/// Signatures of an account after `from_slot` up to the first streamed slot `to_slot`,
+/// newer ones come through the stream
+#[derive(Debug, Default, PartialEq)]
+pub struct SignatureWindow {
+    pub from_slot: u64,
+    pub to_slot: u64,
+    pub max_signatures: usize,
+    /// (signature, slot), newest first as rpc pages them
+    pub signatures: Vec<(String, u64)>,
+    pub is_done: bool,
+    pub gap: Option<ObV2Gap>,
+}
+
+impl SignatureWindow {
+    pub fn new(from_slot: u64, to_slot: u64, max_signatures: usize) -> Self {
+        Self {
+            from_slot,
+            to_slot,
+            max_signatures,
+            ..Default::default()
+        }
+    }
+
+    /// Add a page of signatures, newest first. Failed transactions are left out, the window
+    /// ends at `from_slot`, or at the cap with a gap for the older slots.
+    pub fn push_page(&mut self, page: &[RpcConfirmedTransactionStatusWithSignature]) {
+        if page.is_empty() {
+            self.is_done = true;
+            return;
+        }
+
+        for status in page.iter() {
+            if status.slot <= self.from_slot {
+                self.is_done = true;
+                return;
+            }
+            if status.slot > self.to_slot || status.err.is_some() {
+                continue;
+            }
+            if self.signatures.len() >= self.max_signatures {
+                self.cut(status.slot);
+                return;
+            }
+            self.signatures
+                .push((status.signature.clone(), status.slot));
+        }
+    }
+
+    /// Stop paging, slots from `from_slot` up to `slot` may be missing
+    pub fn cut(&mut self, slot: u64) {
+        self.gap = Some(ObV2Gap {
+            from_slot: self.from_slot,
+            to_slot: slot,
+        });
+        self.is_done = true;
+    }
+
+    /// Oldest slot paged so far, `to_slot` before the first page
+    pub fn oldest_slot(&self) -> u64 {
+        self.signatures
+            .last()
+            .map(|(_, slot)| *slot)
+            .unwrap_or(self.to_slot)
+    }
+}
+
+/// Fetch and parse the window's transactions oldest first, `concurrency` at a time.
+/// A transaction that fails is skipped and its error kept, the others still go out.
+pub async fn replay<F, Fut>(window: &SignatureWindow, concurrency: usize, fetch: F) -> Backfill
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = anyhow::Result<(u64, BotMsg)>>,
+{
+    let results = stream::iter(window.signatures.iter().rev())
+        .map(|(signature, _)| {
+            let fetched = fetch(signature.clone());
+            async move { (signature.clone(), fetched.await) }
+        })
+        .buffered(concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut backfill = Backfill {
+        gap: window.gap.clone(),
+        ..Default::default()
+    };
+    for (signature, result) in results {
+        match result {
+            Ok((slot, msg)) => backfill.msgs.push((slot, signature, msg)),
+            Err(e) => backfill
+                .errors
+                .push(e.context(format!("backfill of {}", signature))),
+        }
+    }
+    backfill
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use solana_sdk::transaction::TransactionError;
+
+    fn status(signature: &str, slot: u64) -> RpcConfirmedTransactionStatusWithSignature {
+        RpcConfirmedTransactionStatusWithSignature {
+            signature: signature.to_string(),
+            slot,
+            err: None,
+            memo: None,
+            block_time: None,
+            confirmation_status: None,
+        }
+    }
+
+    fn signatures(window: &SignatureWindow) -> Vec<&str> {
+        window
+            .signatures
+            .iter()
+            .map(|(signature, _)| signature.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_window() {
+        // Disconnected after slot 100, streaming again from slot 110
+        let mut window = SignatureWindow::new(100, 110, 10);
+
+        let failed = RpcConfirmedTransactionStatusWithSignature {
+            err: Some(TransactionError::AccountInUse),
+            ..status("failed", 107)
+        };
+        window.push_page(&[
+            status("streamed", 111),
+            status("d", 110),
+            status("c", 108),
+            failed,
+        ]);
+        assert!(!window.is_done);
+        assert_eq!(window.oldest_slot(), 108);
+
+        window.push_page(&[status("b", 105), status("a", 101), status("seen", 100)]);
+        assert!(window.is_done);
+        assert_eq!(window.gap, None);
+        // The first streamed slot is backfilled too, the stream skips what was replayed
+        assert_eq!(signatures(&window), vec!["d", "c", "b", "a"]);
+
+        // History ends before the last seen slot
+        let mut window = SignatureWindow::new(100, 110, 10);
+        window.push_page(&[status("a", 105)]);
+        window.push_page(&[]);
+        assert!(window.is_done);
+        assert_eq!(signatures(&window), vec!["a"]);
+    }
+
+    #[test]
+    fn test_window_cap() {
+        let mut window = SignatureWindow::new(100, 110, 2);
+        window.push_page(&[status("c", 109), status("b", 105), status("a", 103)]);
+
+        assert!(window.is_done);
+        assert_eq!(signatures(&window), vec!["c", "b"]);
+        assert_eq!(
+            window.gap,
+            Some(ObV2Gap {
+                from_slot: 100,
+                to_slot: 103
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replay_partial_failure() {
+        let mut window = SignatureWindow::new(100, 110, 10);
+        window.push_page(&[status("c", 109), status("b", 105), status("a", 103)]);
+
+        let backfill = replay(&window, 2, |signature| async move {
+            match signature.as_str() {
+                "b" => anyhow::bail!("rpc error"),
+                "a" => Ok((103, BotMsg::Unimplemented)),
+                _ => Ok((109, BotMsg::Unimplemented)),
+            }
+        })
+        .await;
+
+        // Oldest first, the failed one skipped and kept as error
+        let replayed = backfill
+            .msgs
+            .iter()
+            .map(|(slot, signature, _)| (*slot, signature.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(replayed, vec![(103, "a"), (109, "c")]);
+        assert_eq!(backfill.errors.len(), 1);
+        assert!(backfill.errors[0].to_string().contains("backfill of b"));
+        assert_eq!(backfill.gap, None);
+    }
+}
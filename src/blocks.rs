@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+
+use solana_sdk::clock::UnixTimestamp;
+
+use crate::structs::{MessageTransaction, ParsedBlock};
+
+/// Slots kept in the block time cache
+const BLOCK_TIMES_CAPACITY: usize = 1024;
+
+/// Filters a transaction update matched, with the transaction
+pub type PendingTransaction = (Vec<String>, MessageTransaction);
+
+/// Block times of the block meta stream. Block meta of a slot comes after its transactions,
+/// so transactions are held until then and all of them carry their block time.
+#[derive(Debug, Default)]
+pub struct BlockTimes {
+    pub times: BTreeMap<u64, UnixTimestamp>,
+    /// Transactions waiting for their block meta, by slot in arrival order
+    pub pending: BTreeMap<u64, Vec<PendingTransaction>>,
+}
+
+impl BlockTimes {
+    pub fn get(&self, slot: u64) -> Option<UnixTimestamp> {
+        self.times.get(&slot).copied()
+    }
+
+    /// Hold a transaction until its block meta, it is returned right away if already known
+    pub fn push(
+        &mut self,
+        filters: Vec<String>,
+        mut transaction: MessageTransaction,
+    ) -> Option<PendingTransaction> {
+        if let Some(block_time) = self.get(transaction.slot) {
+            transaction.block_time = Some(block_time);
+            return Some((filters, transaction));
+        }
+
+        self.pending
+            .entry(transaction.slot)
+            .or_default()
+            .push((filters, transaction));
+        None
+    }
+
+    /// Record a block meta and release held transactions of its slot and older ones,
+    /// in slot then arrival order. Older slots without block meta (skipped) get no block time.
+    pub fn on_block(&mut self, block: ParsedBlock) -> Vec<PendingTransaction> {
+        if let Some(block_time) = block.block_time {
+            self.times.insert(block.slot, block_time);
+            while self.times.len() > BLOCK_TIMES_CAPACITY {
+                self.times.pop_first();
+            }
+        }
+
+        let newer = self.pending.split_off(&(block.slot + 1));
+        let ready = std::mem::replace(&mut self.pending, newer);
+
+        ready
+            .into_iter()
+            .flat_map(|(slot, transactions)| {
+                let block_time = self.get(slot);
+                transactions
+                    .into_iter()
+                    .map(move |(filters, mut transaction)| {
+                        transaction.block_time = block_time;
+                        (filters, transaction)
+                    })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn transaction(slot: u64, index: u64) -> MessageTransaction {
+        MessageTransaction {
+            signature: Default::default(),
+            is_vote: false,
+            message: Default::default(),
+            meta: Default::default(),
+            index,
+            slot,
+            block_time: None,
+        }
+    }
+
+    fn block(slot: u64, block_time: Option<UnixTimestamp>) -> ParsedBlock {
+        ParsedBlock { slot, block_time }
+    }
+
+    #[test]
+    fn test_release_on_block_meta() {
+        let mut blocks = BlockTimes::default();
+
+        assert!(blocks.push(vec![], transaction(10, 0)).is_none());
+        assert!(blocks.push(vec![], transaction(11, 0)).is_none());
+        assert!(blocks.push(vec![], transaction(10, 1)).is_none());
+
+        // Slot 10 only, in arrival order
+        let ready = blocks.on_block(block(10, Some(1_000)));
+        assert_eq!(
+            ready
+                .iter()
+                .map(|(_, tx)| (tx.slot, tx.index, tx.block_time))
+                .collect::<Vec<_>>(),
+            vec![(10, 0, Some(1_000)), (10, 1, Some(1_000))]
+        );
+
+        // Known block time, no need to wait
+        let (_, tx) = blocks.push(vec![], transaction(10, 2)).unwrap();
+        assert_eq!(tx.block_time, Some(1_000));
+    }
+
+    #[test]
+    fn test_skipped_slot_released_without_block_time() {
+        let mut blocks = BlockTimes::default();
+
+        assert!(blocks.push(vec![], transaction(10, 0)).is_none());
+        assert!(blocks.push(vec![], transaction(12, 0)).is_none());
+
+        let ready = blocks.on_block(block(11, Some(1_000)));
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].1.block_time, None);
+        assert_eq!(blocks.pending.len(), 1);
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use serde::Deserialize;
+use yellowstone_grpc_proto::prelude::CommitmentLevel;
+
+use crate::structs::ObV2SlotStatus;
+
+/// Slots kept in the status cache
+const SLOT_STATUS_CAPACITY: usize = 1024;
+
+/// Commitment a plugin's output is emitted at
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Commitment {
+    Processed,
+    #[default]
+    Confirmed,
+    Finalized,
+    /// Emitted at processed, then followed by `slot_status` messages once the slot
+    /// is confirmed and finalized, or rolled back if it never makes it
+    ProcessedWithUpgrades,
+}
+
+impl Commitment {
+    /// Slot status required before the output is emitted
+    pub fn level(&self) -> CommitmentLevel {
+        match self {
+            Commitment::Processed | Commitment::ProcessedWithUpgrades => CommitmentLevel::Processed,
+            Commitment::Confirmed => CommitmentLevel::Confirmed,
+            Commitment::Finalized => CommitmentLevel::Finalized,
+        }
+    }
+}
+
+/// Geyser commitment for the given plugin commitments, and whether slot updates are needed.
+/// A subscription has a single commitment: mixed plugins subscribe at processed and
+/// hold updates until their slot reaches each plugin's commitment. At processed, slot
+/// updates also tell which slots were rolled back.
+pub fn subscription(commitments: &[Commitment]) -> (CommitmentLevel, bool) {
+    let Some(first) = commitments.first() else {
+        return (Commitment::default().level(), false);
+    };
+
+    let is_uniform = commitments.iter().all(|commitment| commitment == first);
+    let level = if is_uniform {
+        first.level()
+    } else {
+        CommitmentLevel::Processed
+    };
+    (level, level == CommitmentLevel::Processed)
+}
+
+/// Outcome of a slot status update
+#[derive(Debug)]
+pub struct SlotUpdate<T> {
+    /// Held updates whose slot reached their commitment, in slot then arrival order
+    pub released: Vec<T>,
+    /// (indicator name, slot, status) to publish for `ProcessedWithUpgrades` indicators
+    pub notices: Vec<(String, u64, ObV2SlotStatus)>,
+    /// Slots found to be on a dead fork
+    pub rolled_back: Vec<u64>,
+}
+
+impl<T> Default for SlotUpdate<T> {
+    fn default() -> Self {
+        Self {
+            released: vec![],
+            notices: vec![],
+            rolled_back: vec![],
+        }
+    }
+}
+
+/// Holds updates until their slot reaches the commitment of the plugin they are for,
+/// and follows slots emitted early by `ProcessedWithUpgrades` plugins.
+///
+/// A confirmed slot rolls back the slots between it and its parent, they were on
+/// another branch. Finalization of a slot settles every older one: confirmed slots
+/// are finalized with it, the others were on a dead fork and are rolled back.
+#[derive(Debug)]
+pub struct CommitmentGate<T> {
+    /// Commitment of the subscription, every update has at least reached it
+    pub subscribed: CommitmentLevel,
+    /// Highest status of recent slots
+    pub status: BTreeMap<u64, CommitmentLevel>,
+    /// Updates waiting for their slot, with the status they need, in arrival order
+    pub pending: BTreeMap<u64, Vec<(CommitmentLevel, T)>>,
+    /// Indicators published at processed by `ProcessedWithUpgrades` plugins, by slot
+    pub tracked: BTreeMap<u64, HashSet<String>>,
+}
+
+impl<T> CommitmentGate<T> {
+    pub fn new(subscribed: CommitmentLevel) -> Self {
+        Self {
+            subscribed,
+            status: BTreeMap::new(),
+            pending: BTreeMap::new(),
+            tracked: BTreeMap::new(),
+        }
+    }
+
+    /// Highest status a slot is known to have reached
+    pub fn reached(&self, slot: u64) -> CommitmentLevel {
+        match self.status.get(&slot) {
+            Some(status) => (*status).max(self.subscribed),
+            None => self.subscribed,
+        }
+    }
+
+    pub fn is_ready(&self, slot: u64, commitment: Commitment) -> bool {
+        self.reached(slot) >= commitment.level()
+    }
+
+    /// Hold an update until its slot reaches `commitment`
+    pub fn hold(&mut self, slot: u64, commitment: Commitment, update: T) {
+        self.pending
+            .entry(slot)
+            .or_default()
+            .push((commitment.level(), update));
+    }
+
+    /// Follow a slot an indicator was published at, to send its status upgrades
+    pub fn track(&mut self, slot: u64, name: String) {
+        if self.reached(slot) < CommitmentLevel::Finalized {
+            self.tracked.entry(slot).or_default().insert(name);
+        }
+    }
+
+    /// Record a slot status, release what waited for it and report status changes
+    pub fn on_slot(
+        &mut self,
+        slot: u64,
+        parent: Option<u64>,
+        status: CommitmentLevel,
+    ) -> SlotUpdate<T> {
+        let mut update = SlotUpdate::default();
+        if self.status.get(&slot).is_some_and(|known| *known >= status) {
+            return update;
+        }
+
+        self.set_status(slot, status);
+
+        let settled = match status {
+            CommitmentLevel::Finalized => Some(0..slot),
+            CommitmentLevel::Confirmed => parent.map(|parent| parent + 1..slot),
+            _ => None,
+        };
+        if let Some(range) = settled {
+            let older = self
+                .status
+                .range(range.clone())
+                .map(|(slot, _)| *slot)
+                .chain(self.pending.range(range.clone()).map(|(slot, _)| *slot))
+                .chain(self.tracked.range(range).map(|(slot, _)| *slot))
+                .collect::<BTreeSet<_>>();
+            for older in older {
+                if self.reached(older) < CommitmentLevel::Confirmed {
+                    self.roll_back(older, &mut update);
+                } else if status == CommitmentLevel::Finalized
+                    && self.reached(older) < CommitmentLevel::Finalized
+                {
+                    self.set_status(older, CommitmentLevel::Finalized);
+                    self.settle(older, CommitmentLevel::Finalized, &mut update);
+                }
+            }
+        }
+
+        self.settle(slot, status, &mut update);
+        update
+    }
+
+    fn set_status(&mut self, slot: u64, status: CommitmentLevel) {
+        self.status.insert(slot, status);
+        while self.status.len() > SLOT_STATUS_CAPACITY {
+            self.status.pop_first();
+        }
+    }
+
+    fn settle(&mut self, slot: u64, status: CommitmentLevel, update: &mut SlotUpdate<T>) {
+        if let Some(held) = self.pending.remove(&slot) {
+            let (ready, waiting): (Vec<_>, Vec<_>) =
+                held.into_iter().partition(|(level, _)| *level <= status);
+            update
+                .released
+                .extend(ready.into_iter().map(|(_, update)| update));
+            if !waiting.is_empty() {
+                self.pending.insert(slot, waiting);
+            }
+        }
+
+        let (names, notice) = match status {
+            CommitmentLevel::Confirmed => {
+                (self.tracked.get(&slot).cloned(), ObV2SlotStatus::Confirmed)
+            }
+            CommitmentLevel::Finalized => (self.tracked.remove(&slot), ObV2SlotStatus::Finalized),
+            _ => return,
+        };
+        for name in names.into_iter().flatten() {
+            update.notices.push((name, slot, notice));
+        }
+    }
+
+    fn roll_back(&mut self, slot: u64, update: &mut SlotUpdate<T>) {
+        self.status.remove(&slot);
+        self.pending.remove(&slot);
+        update.rolled_back.push(slot);
+        for name in self.tracked.remove(&slot).into_iter().flatten() {
+            update
+                .notices
+                .push((name, slot, ObV2SlotStatus::RolledBack));
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscription() {
+        assert_eq!(subscription(&[]), (CommitmentLevel::Confirmed, false));
+        assert_eq!(
+            subscription(&[Commitment::Finalized, Commitment::Finalized]),
+            (CommitmentLevel::Finalized, false)
+        );
+        assert_eq!(
+            subscription(&[Commitment::Processed]),
+            (CommitmentLevel::Processed, true)
+        );
+        assert_eq!(
+            subscription(&[Commitment::Processed, Commitment::Confirmed]),
+            (CommitmentLevel::Processed, true)
+        );
+        assert_eq!(
+            subscription(&[Commitment::ProcessedWithUpgrades]),
+            (CommitmentLevel::Processed, true)
+        );
+    }
+
+    #[test]
+    fn test_hold_until_commitment() {
+        let mut gate = CommitmentGate::new(CommitmentLevel::Processed);
+
+        assert!(gate.is_ready(10, Commitment::Processed));
+        assert!(!gate.is_ready(10, Commitment::Confirmed));
+        gate.hold(10, Commitment::Finalized, 1);
+        gate.hold(10, Commitment::Confirmed, 2);
+
+        assert!(gate
+            .on_slot(10, None, CommitmentLevel::Processed)
+            .released
+            .is_empty());
+        assert_eq!(
+            gate.on_slot(10, Some(9), CommitmentLevel::Confirmed)
+                .released,
+            vec![2]
+        );
+        assert!(gate.is_ready(10, Commitment::Confirmed));
+        assert_eq!(
+            gate.on_slot(10, Some(9), CommitmentLevel::Finalized)
+                .released,
+            vec![1]
+        );
+        assert!(gate.pending.is_empty());
+    }
+
+    #[test]
+    fn test_upgrades_and_rollback() {
+        let mut gate = CommitmentGate::new(CommitmentLevel::Processed);
+
+        gate.track(10, "books".to_string());
+        gate.track(11, "books".to_string());
+        gate.hold(11, Commitment::Confirmed, 1);
+
+        let update = gate.on_slot(10, Some(9), CommitmentLevel::Confirmed);
+        assert_eq!(
+            update.notices,
+            vec![("books".to_string(), 10, ObV2SlotStatus::Confirmed)]
+        );
+
+        // Slot 11 was never confirmed, it was on a dead fork
+        let update = gate.on_slot(12, Some(10), CommitmentLevel::Finalized);
+        assert!(update.released.is_empty());
+        assert_eq!(
+            update.notices,
+            vec![
+                ("books".to_string(), 10, ObV2SlotStatus::Finalized),
+                ("books".to_string(), 11, ObV2SlotStatus::RolledBack),
+            ]
+        );
+        assert_eq!(update.rolled_back, vec![11]);
+        assert!(gate.pending.is_empty());
+        assert!(gate.tracked.is_empty());
+    }
+
+    #[test]
+    fn test_other_branch_confirmed() {
+        let mut gate = CommitmentGate::<u32>::new(CommitmentLevel::Processed);
+
+        gate.on_slot(10, Some(9), CommitmentLevel::Processed);
+        gate.on_slot(11, Some(10), CommitmentLevel::Processed);
+        gate.on_slot(12, Some(10), CommitmentLevel::Processed);
+
+        // 12 builds on 10, 11 is off the confirmed branch
+        let update = gate.on_slot(12, Some(10), CommitmentLevel::Confirmed);
+        assert_eq!(update.rolled_back, vec![11]);
+        assert!(gate
+            .on_slot(13, Some(12), CommitmentLevel::Confirmed)
+            .rolled_back
+            .is_empty());
+    }
+}
@@ -0,0 +1,327 @@
+use crate::commitment::Commitment;
+use crate::obv2::ObV2MarketInfo;
+use crate::supervisor::Backoff;
+use crate::units::Units;
+use anyhow::Context;
+use serde::Deserialize;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+pub const OPENBOOK_V2_PROGRAM_ID: &str = "opnb2LAfJYbRMAHHvqjCwQxanZn7ReEHp1k81EohpZb";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    pub markets: Vec<MarketConfig>,
+    #[serde(default)]
+    pub sinks: SinksConfig,
+    #[serde(default)]
+    pub reconnect: Backoff,
+    /// Open orders accounts (or owners) whose fills go to `ob_v2_{name}_my_fills`,
+    /// tracked open orders accounts of a market are always watched
+    #[serde(default)]
+    pub watchlist: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SinksConfig {
+    /// Print every message as a json line on stdout
+    #[serde(default)]
+    pub stdout: bool,
+    /// Units of the values printed on stdout
+    #[serde(default)]
+    pub stdout_units: Units,
+    pub file: Option<FileSinkConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FileSinkConfig {
+    pub path: String,
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: u64,
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+    #[serde(default)]
+    pub units: Units,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PluginKind {
+    Books,
+    Events,
+    Transactions,
+}
+
+/// Commitment of each plugin of a market, confirmed unless set
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct PluginCommitments {
+    #[serde(default)]
+    pub books: Commitment,
+    #[serde(default)]
+    pub events: Commitment,
+    #[serde(default)]
+    pub transactions: Commitment,
+    #[serde(default)]
+    pub open_orders: Commitment,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MarketConfig {
+    /// Short market name, used as `ob_v2_{name}_{plugin}` indicator prefix
+    pub name: String,
+    #[serde(default = "default_program_id")]
+    pub program_id: String,
+    pub market: String,
+    pub bids: Option<String>,
+    pub asks: Option<String>,
+    pub event_heap: Option<String>,
+    pub base_decimals: Option<u8>,
+    pub quote_decimals: Option<u8>,
+    pub base_lot_size: Option<u64>,
+    pub quote_lot_size: Option<u64>,
+    pub plugins: Vec<PluginKind>,
+    /// Our open orders accounts on this market, tracked as `ob_v2_{name}_oo_{account}`
+    #[serde(default)]
+    pub open_orders: Vec<String>,
+    /// Book updates between two full snapshots, diffs are sent in between
+    #[serde(default = "default_snapshot_interval")]
+    pub snapshot_interval: u64,
+    /// Levels per side in the two-sided market view
+    #[serde(default = "default_depth")]
+    pub depth: usize,
+    /// Leave expired orders and pegged orders past their limit out of the book orders
+    #[serde(default)]
+    pub skip_invalid_orders: bool,
+    #[serde(default)]
+    pub commitment: PluginCommitments,
+}
+
+fn default_program_id() -> String {
+    OPENBOOK_V2_PROGRAM_ID.to_string()
+}
+
+fn default_snapshot_interval() -> u64 {
+    100
+}
+
+fn default_depth() -> usize {
+    10
+}
+
+fn default_max_bytes() -> u64 {
+    100 * 1024 * 1024
+}
+
+fn default_max_files() -> usize {
+    10
+}
+
+impl Config {
+    /// Load config from a `.toml` or `.json` file and validate every market entry
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed to read config {}", path.display()))?;
+
+        let config: Config = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&content)
+                .with_context(|| format!("failed to parse config {}", path.display()))?,
+            Some("toml") => toml::from_str(&content)
+                .with_context(|| format!("failed to parse config {}", path.display()))?,
+            _ => anyhow::bail!(
+                "unsupported config format {}, expected .toml or .json",
+                path.display()
+            ),
+        };
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.markets.is_empty() {
+            anyhow::bail!("config has no markets");
+        }
+
+        let mut names = HashSet::new();
+        for market in self.markets.iter() {
+            if !names.insert(market.name.as_str()) {
+                anyhow::bail!("market `{}`: duplicated market name", market.name);
+            }
+            market.validate()?;
+        }
+
+        for account in self.watchlist.iter() {
+            Pubkey::from_str(account)
+                .map_err(|e| anyhow::anyhow!("invalid watchlist pubkey `{}`: {}", account, e))?;
+        }
+
+        if let Some(file) = &self.sinks.file {
+            if file.max_bytes == 0 {
+                anyhow::bail!("file sink `{}`: max_bytes must be non-zero", file.path);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Track an open orders account (e.g. from `OOS_KEY`) on the configured market it belongs to
+    pub fn attach_open_orders(&mut self, account: &str, market: &str) -> anyhow::Result<()> {
+        let Some(config) = self
+            .markets
+            .iter_mut()
+            .find(|config| config.market == market)
+        else {
+            anyhow::bail!(
+                "open orders `{}`: market {} is not configured",
+                account,
+                market
+            );
+        };
+
+        if !config
+            .open_orders
+            .iter()
+            .any(|configured| configured == account)
+        {
+            config.open_orders.push(account.to_string());
+        }
+        Ok(())
+    }
+}
+
+impl MarketConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.name.is_empty() {
+            anyhow::bail!("market `{}`: name must not be empty", self.market);
+        }
+
+        self.pubkey("program_id", &self.program_id)?;
+        self.pubkey("market", &self.market)?;
+
+        if self.plugins.is_empty() {
+            anyhow::bail!("market `{}`: no plugins configured", self.name);
+        }
+        if self.base_lot_size == Some(0) || self.quote_lot_size == Some(0) {
+            anyhow::bail!("market `{}`: lot sizes must be non-zero", self.name);
+        }
+
+        // Accounts are optional here, they are discovered from the market account
+        for (field, value) in [
+            ("bids", &self.bids),
+            ("asks", &self.asks),
+            ("event_heap", &self.event_heap),
+        ] {
+            if let Some(value) = value {
+                self.pubkey(field, value)?;
+            }
+        }
+        for account in self.open_orders.iter() {
+            self.pubkey("open_orders", account)?;
+        }
+
+        Ok(())
+    }
+
+    /// Check everything required by the configured plugins is known
+    pub fn validate_resolved(&self) -> anyhow::Result<()> {
+        self.validate()?;
+
+        for plugin in self.plugins.iter() {
+            match plugin {
+                PluginKind::Books => {
+                    self.required("bids", &self.bids)?;
+                    self.required("asks", &self.asks)?;
+                }
+                PluginKind::Events => {
+                    self.required("event_heap", &self.event_heap)?;
+                }
+                PluginKind::Transactions => {}
+            }
+        }
+
+        for (field, value) in [
+            ("base_decimals", self.base_decimals.map(|v| v as u64)),
+            ("quote_decimals", self.quote_decimals.map(|v| v as u64)),
+            ("base_lot_size", self.base_lot_size),
+            ("quote_lot_size", self.quote_lot_size),
+        ] {
+            if value.is_none() {
+                anyhow::bail!("market `{}`: missing {}", self.name, field);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fill accounts, decimals and lot sizes from the on-chain market.
+    /// Values set in the config must match the chain, so a typo fails at startup.
+    pub fn discover(&mut self, info: &ObV2MarketInfo) -> anyhow::Result<()> {
+        if info.program_id != self.program_id {
+            anyhow::bail!(
+                "market `{}`: account is owned by {}, expected {}",
+                self.name,
+                info.program_id,
+                self.program_id
+            );
+        }
+
+        self.bids = Some(self.merge("bids", &self.bids, info.bids.clone())?);
+        self.asks = Some(self.merge("asks", &self.asks, info.asks.clone())?);
+        self.event_heap =
+            Some(self.merge("event_heap", &self.event_heap, info.event_heap.clone())?);
+        self.base_decimals =
+            Some(self.merge("base_decimals", &self.base_decimals, info.base_decimals)?);
+        self.quote_decimals =
+            Some(self.merge("quote_decimals", &self.quote_decimals, info.quote_decimals)?);
+        self.base_lot_size =
+            Some(self.merge("base_lot_size", &self.base_lot_size, info.base_lot_size)?);
+        self.quote_lot_size =
+            Some(self.merge("quote_lot_size", &self.quote_lot_size, info.quote_lot_size)?);
+
+        self.validate_resolved()
+    }
+
+    /// Indicator name of the given plugin, e.g. `ob_v2_sol_usdc_bids`
+    pub fn indicator_name(&self, suffix: &str) -> String {
+        format!("ob_v2_{}_{}", self.name, suffix)
+    }
+
+    fn merge<T>(&self, field: &str, configured: &Option<T>, on_chain: T) -> anyhow::Result<T>
+    where
+        T: PartialEq + std::fmt::Display + Clone,
+    {
+        match configured {
+            Some(value) if *value != on_chain => anyhow::bail!(
+                "market `{}`: configured {} `{}` does not match on-chain `{}`",
+                self.name,
+                field,
+                value,
+                on_chain
+            ),
+            _ => Ok(on_chain),
+        }
+    }
+
+    fn pubkey(&self, field: &str, value: &str) -> anyhow::Result<Pubkey> {
+        Pubkey::from_str(value).map_err(|e| {
+            anyhow::anyhow!(
+                "market `{}`: invalid {} pubkey `{}`: {}",
+                self.name,
+                field,
+                value,
+                e
+            )
+        })
+    }
+
+    fn required(&self, field: &str, value: &Option<String>) -> anyhow::Result<Pubkey> {
+        match value {
+            Some(value) => self.pubkey(field, value),
+            None => anyhow::bail!("market `{}`: missing {} account", self.name, field),
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use crate::structs::ObV2Errors;
+
+/// Indicator name of the published error counts
+pub const ERRORS_INDICATOR: &str = "errors";
+
+/// Errors of decoding geyser updates and plugin data, none of them should stop the feed
+#[derive(Debug, Error)]
+pub enum GeyserError {
+    #[error("{0} should be defined")]
+    MissingField(&'static str),
+
+    #[error("invalid pubkey: {0}")]
+    InvalidPubkey(String),
+
+    #[error("account index {0} out of range")]
+    AccountIndex(usize),
+
+    #[error("account {0} is not part of the transaction")]
+    AccountNotInTransaction(String),
+
+    #[error("account {account} data too small: {len} bytes, expected {expected}")]
+    AccountTooSmall {
+        account: String,
+        len: usize,
+        expected: usize,
+    },
+
+    #[error("unknown event type {0}")]
+    UnknownEventType(u8),
+
+    #[error("invalid program data: {0}")]
+    InvalidProgramData(String),
+
+    #[error("no plugin for filter {0}")]
+    UnknownFilter(String),
+
+    #[error("no cluster time for slot {0}")]
+    NoClusterTime(u64),
+
+    #[error("invalid clock sysvar: {0}")]
+    InvalidClock(String),
+}
+
+/// Error count per plugin name, failed updates are skipped and counted instead of panicking
+#[derive(Debug, Default, Clone)]
+pub struct ErrorCounters {
+    pub counts: HashMap<String, u64>,
+    /// Counts changed since they were last taken
+    pub is_changed: bool,
+}
+
+impl ErrorCounters {
+    pub fn record(&mut self, name: &str, error: &anyhow::Error) {
+        let count = self.counts.entry(name.to_string()).or_default();
+        *count += 1;
+        self.is_changed = true;
+        tracing::error!("{}: {} (errors: {})", name, error, count);
+    }
+
+    pub fn get(&self, name: &str) -> u64 {
+        self.counts.get(name).copied().unwrap_or_default()
+    }
+
+    /// Counts to publish, none if nothing was recorded since the last call
+    pub fn take_changed(&mut self) -> Option<ObV2Errors> {
+        if !self.is_changed {
+            return None;
+        }
+        self.is_changed = false;
+
+        Some(ObV2Errors {
+            counts: self
+                .counts
+                .iter()
+                .map(|(name, count)| (name.clone(), *count))
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_changed() {
+        let mut errors = ErrorCounters::default();
+        assert_eq!(errors.take_changed(), None);
+
+        let e = GeyserError::UnknownEventType(9).into();
+        errors.record("events", &e);
+        errors.record("events", &e);
+        errors.record("bids", &e);
+        let changed = errors.take_changed().unwrap();
+        assert_eq!(changed.counts.get("events"), Some(&2));
+        assert_eq!(changed.counts.get("bids"), Some(&1));
+
+        // Published once per change
+        assert_eq!(errors.take_changed(), None);
+        errors.record("bids", &e);
+        assert_eq!(errors.take_changed().unwrap().counts.get("bids"), Some(&2));
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use crate::structs::ObV2Gap;
+
+/// Tracks the last update of every plugin across geyser sessions,
+/// so updates missed while disconnected can be reported and backfilled
+#[derive(Debug, Default, Clone)]
+pub struct GapTracker {
+    /// Last (slot, write_version) seen per plugin name
+    pub last_seen: HashMap<String, (u64, u64)>,
+    /// Highest slot seen by any plugin
+    pub last_slot: Option<u64>,
+}
+
+impl GapTracker {
+    /// Record an update of a plugin
+    pub fn observe(&mut self, name: &str, slot: u64, write_version: u64) {
+        let last = self.last_seen.entry(name.to_string()).or_default();
+        if (slot, write_version) > *last {
+            *last = (slot, write_version);
+        }
+        self.last_slot = Some(self.last_slot.unwrap_or_default().max(slot));
+    }
+
+    /// Plugins were resnapshotted (or backfilled) up to the given slot
+    pub fn resync(&mut self, names: &[String], slot: u64) {
+        for name in names.iter() {
+            self.observe(name, slot, 0);
+        }
+        self.last_slot = Some(self.last_slot.unwrap_or_default().max(slot));
+    }
+
+    /// Gap of a plugin between its last update and the slot the stream resumed at.
+    /// None on the first session, nothing can be missed yet, and when the stream
+    /// resumed right after the last seen slot.
+    pub fn gap(&self, name: &str, resumed_slot: u64) -> Option<ObV2Gap> {
+        let last_slot = self.last_slot?;
+        let from_slot = match self.last_seen.get(name) {
+            Some((slot, _)) => *slot,
+            None => last_slot,
+        };
+        if resumed_slot <= from_slot + 1 {
+            return None;
+        }
+
+        Some(ObV2Gap {
+            from_slot,
+            to_slot: resumed_slot,
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gap() {
+        let mut tracker = GapTracker::default();
+        assert_eq!(tracker.gap("books", 100), None);
+
+        tracker.observe("books", 100, 1);
+        assert_eq!(
+            tracker.gap("books", 120),
+            Some(ObV2Gap {
+                from_slot: 100,
+                to_slot: 120
+            })
+        );
+        // Plugins without updates yet start from the highest slot seen
+        assert_eq!(
+            tracker.gap("fills", 120),
+            Some(ObV2Gap {
+                from_slot: 100,
+                to_slot: 120
+            })
+        );
+    }
+
+    #[test]
+    fn test_no_gap_on_quick_reconnect() {
+        let mut tracker = GapTracker::default();
+        tracker.observe("books", 100, 1);
+
+        assert_eq!(tracker.gap("books", 100), None);
+        assert_eq!(tracker.gap("books", 101), None);
+        // The stream may resume behind the last update seen
+        assert_eq!(tracker.gap("books", 90), None);
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::{HashMap, VecDeque};
+
+use solana_sdk::pubkey::Pubkey;
+
+use crate::structs::Account;
+
+/// Writes kept per account at processed commitment, enough to get back before a fork
+pub const ACCOUNT_HISTORY_CAPACITY: usize = 32;
+
+/// Filters an account update matched, with the account
+pub type AccountWrite = (Vec<String>, Account);
+
+/// Recent writes of every subscribed account, newest last.
+/// Stale writes (lower `write_version`) are rejected, and writes of rolled back slots
+/// are dropped so the account can be restored to its last write on the surviving branch.
+#[derive(Debug)]
+pub struct AccountHistory {
+    pub capacity: usize,
+    pub writes: HashMap<Pubkey, VecDeque<AccountWrite>>,
+}
+
+impl AccountHistory {
+    /// Keep `capacity` writes per account, at least the last one to order writes
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            writes: HashMap::new(),
+        }
+    }
+
+    /// Record a write, false if it is not newer than the last write of the account
+    pub fn push(&mut self, filters: &[String], account: &Account) -> bool {
+        let writes = self.writes.entry(account.pubkey).or_default();
+        if let Some((_, last)) = writes.back() {
+            if account.write_version <= last.write_version {
+                return false;
+            }
+        }
+
+        writes.push_back((filters.to_vec(), account.clone()));
+        while writes.len() > self.capacity {
+            writes.pop_front();
+        }
+        true
+    }
+
+    /// Drop writes of rolled back slots. Accounts whose last write was rolled back are
+    /// returned at their previous write, to be extracted again.
+    pub fn roll_back(&mut self, slots: &[u64]) -> Vec<AccountWrite> {
+        let mut restored = vec![];
+        if slots.is_empty() {
+            return restored;
+        }
+
+        for (pubkey, writes) in self.writes.iter_mut() {
+            let Some((_, last)) = writes.back() else {
+                continue;
+            };
+            let is_last_dead = slots.contains(&last.slot);
+
+            writes.retain(|(_, account)| !slots.contains(&account.slot));
+            if !is_last_dead {
+                continue;
+            }
+
+            match writes.back() {
+                Some(write) => restored.push(write.clone()),
+                None => tracing::warn!(
+                    "{}: no write before rolled back slots {:?}, waiting for the next update",
+                    pubkey,
+                    slots
+                ),
+            }
+        }
+
+        restored
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn account(slot: u64, write_version: u64, data: u8) -> Account {
+        Account {
+            is_startup: false,
+            slot,
+            pubkey: Pubkey::default(),
+            lamports: 0,
+            owner: Pubkey::default(),
+            executable: false,
+            rent_epoch: 0,
+            data: vec![data],
+            write_version,
+            txn_signature: String::new(),
+            block_time: None,
+        }
+    }
+
+    #[test]
+    fn test_stale_write_ignored() {
+        let mut history = AccountHistory::new(ACCOUNT_HISTORY_CAPACITY);
+
+        assert!(history.push(&[], &account(10, 5, 1)));
+        assert!(!history.push(&[], &account(10, 4, 2)));
+        assert!(!history.push(&[], &account(10, 5, 1)));
+        assert!(history.push(&[], &account(11, 6, 3)));
+    }
+
+    #[test]
+    fn test_restore_before_rolled_back_slot() {
+        let mut history = AccountHistory::new(ACCOUNT_HISTORY_CAPACITY);
+        history.push(&["bids".to_string()], &account(10, 1, 1));
+        history.push(&["bids".to_string()], &account(11, 2, 2));
+
+        // Nothing to restore when the last write survives
+        assert!(history.roll_back(&[9]).is_empty());
+
+        let restored = history.roll_back(&[11]);
+        assert_eq!(restored.len(), 1);
+        assert_eq!(restored[0].0, vec!["bids".to_string()]);
+        assert_eq!(restored[0].1.data, vec![1]);
+
+        // The branch continues from slot 10
+        assert!(history.push(&[], &account(12, 3, 3)));
+    }
+}
@@ -0,0 +1,285 @@
+use std::str::FromStr;
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use solana_sdk::pubkey::Pubkey;
+
+const PROGRAM_DATA: &str = "Program data:";
+/// Messages logged on behalf of a program, whatever they contain
+const PROGRAM_MESSAGES: [&str; 3] = ["Program log:", "Program return:", PROGRAM_DATA];
+const LOG_TRUNCATED: &str = "Log truncated";
+
+/// `Program data:` entry with the instruction that emitted it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgramData {
+    pub program_id: String,
+    /// Top-level instruction index
+    pub index: usize,
+    /// Index in the inner instructions of `index`, none for the top-level instruction itself
+    pub inner_index: Option<usize>,
+    /// Invoke depth, 1 for top-level instructions
+    pub depth: usize,
+    /// Whether the emitting invocation returned success, false when truncated logs cut it off
+    pub is_success: bool,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParsedLogs {
+    pub data: Vec<ProgramData>,
+    /// Logs were cut by the runtime, later entries (and their attribution) are missing
+    pub is_truncated: bool,
+}
+
+struct Frame {
+    program_id: String,
+    index: usize,
+    inner_index: Option<usize>,
+    depth: usize,
+    /// Positions in `ParsedLogs::data` emitted by this invocation
+    data: Vec<usize>,
+}
+
+/// Walk transaction logs keeping the invoke stack, so every `Program data:` entry is
+/// attributed to its (outer, inner) instruction, including CPIs at any depth.
+///
+/// Inner indexes follow the runtime's flattened inner instruction list: every invoke
+/// deeper than 1 takes the next index of the current top-level instruction.
+pub fn parse_logs(logs: &[String]) -> ParsedLogs {
+    let mut parsed = ParsedLogs::default();
+    let mut stack: Vec<Frame> = vec![];
+    let mut next_index: usize = 0;
+    let mut next_inner_index = 0;
+
+    for log in logs.iter() {
+        if log.starts_with(LOG_TRUNCATED) {
+            parsed.is_truncated = true;
+            continue;
+        }
+
+        if let Some((program_id, depth)) = parse_invoke(log) {
+            let (index, inner_index) = if depth == 1 {
+                stack.clear();
+                next_index += 1;
+                next_inner_index = 0;
+                (next_index - 1, None)
+            } else {
+                let index = stack
+                    .first()
+                    .map(|frame| frame.index)
+                    .unwrap_or(next_index.saturating_sub(1));
+                next_inner_index += 1;
+                (index, Some(next_inner_index - 1))
+            };
+
+            stack.push(Frame {
+                program_id: program_id.to_string(),
+                index,
+                inner_index,
+                depth,
+                data: vec![],
+            });
+            continue;
+        }
+
+        if let Some((program_id, is_success)) = parse_result(log) {
+            // Unwind to the returning program, frames above it never returned
+            while let Some(frame) = stack.pop() {
+                let is_returning = frame.program_id == program_id;
+                for position in frame.data.iter() {
+                    parsed.data[*position].is_success = is_returning && is_success;
+                }
+                if is_returning {
+                    break;
+                }
+            }
+            continue;
+        }
+
+        if let Some(payload) = log.strip_prefix(PROGRAM_DATA) {
+            let Some(frame) = stack.last_mut() else {
+                continue;
+            };
+            let Ok(data) = BASE64_STANDARD.decode(payload.trim()) else {
+                tracing::warn!("undecodable program data: {}", log);
+                continue;
+            };
+
+            frame.data.push(parsed.data.len());
+            parsed.data.push(ProgramData {
+                program_id: frame.program_id.clone(),
+                index: frame.index,
+                inner_index: frame.inner_index,
+                depth: frame.depth,
+                // Until the invocation returns
+                is_success: false,
+                data,
+            });
+        }
+    }
+
+    parsed
+}
+
+/// `Program <id> invoke [<depth>]`
+fn parse_invoke(log: &str) -> Option<(&str, usize)> {
+    let (program_id, rest) = split_program_id(log)?;
+    let depth = rest
+        .strip_prefix("invoke [")?
+        .strip_suffix(']')?
+        .parse()
+        .ok()?;
+    Some((program_id, depth))
+}
+
+/// `Program <id> success` or `Program <id> failed: <error>`
+fn parse_result(log: &str) -> Option<(&str, bool)> {
+    let (program_id, rest) = split_program_id(log)?;
+    if rest == "success" {
+        return Some((program_id, true));
+    }
+    rest.strip_prefix("failed: ")?;
+    Some((program_id, false))
+}
+
+/// Program id and the rest of a runtime line, `Program <base58 pubkey> <rest>`.
+/// Program messages can end like runtime lines (e.g. `Program log: order failed: ...`).
+fn split_program_id(log: &str) -> Option<(&str, &str)> {
+    if PROGRAM_MESSAGES
+        .iter()
+        .any(|prefix| log.starts_with(prefix))
+    {
+        return None;
+    }
+    let (program_id, rest) = log.strip_prefix("Program ")?.split_once(' ')?;
+    Pubkey::from_str(program_id).ok()?;
+    Some((program_id, rest))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    const OPENBOOK: &str = "opnb2LAfJYbRMAHHvqjCwQxanZn7ReEHp1k81EohpZb";
+
+    fn fixture(content: &str) -> Vec<String> {
+        content.lines().map(|line| line.to_string()).collect()
+    }
+
+    fn openbook_data(parsed: &ParsedLogs) -> Vec<(usize, Option<usize>, bool, Vec<u8>)> {
+        parsed
+            .data
+            .iter()
+            .filter(|data| data.program_id == OPENBOOK)
+            .map(|data| {
+                (
+                    data.index,
+                    data.inner_index,
+                    data.is_success,
+                    data.data.clone(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_top_level_place_order() {
+        let parsed = parse_logs(&fixture(include_str!("../fixtures/logs/place_order.log")));
+
+        assert!(!parsed.is_truncated);
+        assert_eq!(
+            openbook_data(&parsed),
+            vec![
+                (2, None, true, vec![1, 2, 3]),
+                (2, None, true, vec![4, 5, 6]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cpi_from_aggregator() {
+        let parsed = parse_logs(&fixture(include_str!("../fixtures/logs/jupiter_route.log")));
+
+        assert!(!parsed.is_truncated);
+        // Token transfers take inner indexes 0 and 1, openbook is the third inner instruction
+        assert_eq!(
+            openbook_data(&parsed),
+            vec![(1, Some(2), true, vec![1, 2, 3])]
+        );
+        assert_eq!(parsed.data[0].depth, 2);
+    }
+
+    #[test]
+    fn test_failed_invocation() {
+        let parsed = parse_logs(&fixture(include_str!("../fixtures/logs/failed_cpi.log")));
+
+        // Data of the failed call is kept but flagged
+        assert_eq!(
+            openbook_data(&parsed),
+            vec![(1, Some(0), false, vec![1, 2, 3])]
+        );
+    }
+
+    #[test]
+    fn test_unwind_to_returning_program() {
+        // The inner invocation never logged its result, the outer failure closes both
+        let logs = fixture(&format!(
+            "Program {a} invoke [1]\n\
+             Program {b} invoke [2]\n\
+             Program data: AQID\n\
+             Program {a} failed: out of compute\n\
+             Program {b} invoke [1]\n\
+             Program data: BAUG\n\
+             Program {b} success",
+            a = "JUP6LkbZbjS1jKKwapdHNy74zcZ3tLUZoi5QNyVTaV4",
+            b = OPENBOOK,
+        ));
+        let parsed = parse_logs(&logs);
+
+        assert_eq!(parsed.data.len(), 2);
+        assert_eq!(
+            (parsed.data[0].index, parsed.data[0].inner_index),
+            (0, Some(0))
+        );
+        assert!(!parsed.data[0].is_success);
+        assert_eq!(
+            (parsed.data[1].index, parsed.data[1].inner_index),
+            (1, None)
+        );
+        assert!(parsed.data[1].is_success);
+    }
+
+    #[test]
+    fn test_program_messages() {
+        let parsed = parse_logs(&fixture(include_str!(
+            "../fixtures/logs/lookalike_results.log"
+        )));
+
+        // Logged messages ending like results or invokes don't move the stack
+        assert_eq!(
+            openbook_data(&parsed),
+            vec![
+                (1, None, true, vec![1, 2, 3]),
+                (1, None, true, vec![4, 5, 6])
+            ]
+        );
+        assert_eq!(
+            parse_result("Program log: order 7 failed: would self trade"),
+            None
+        );
+        assert_eq!(parse_result("Program not a pubkey success"), None);
+        assert_eq!(parse_invoke("Program log: retry invoke [2]"), None);
+    }
+
+    #[test]
+    fn test_truncated_logs() {
+        let parsed = parse_logs(&fixture(include_str!("../fixtures/logs/truncated.log")));
+
+        assert!(parsed.is_truncated);
+        // The invocation never returned in the logs, its data is kept for callers
+        // knowing whether the transaction succeeded
+        assert_eq!(
+            openbook_data(&parsed),
+            vec![(0, None, false, vec![1, 2, 3])]
+        );
+    }
+}
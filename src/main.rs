@@ -1,17 +1,42 @@
+pub mod backfill;
+pub mod blocks;
+pub mod commitment;
+pub mod config;
+pub mod error;
+pub mod gap;
+pub mod history;
+pub mod log_parser;
 pub mod obv2;
+pub mod registry;
+pub mod sinks;
 pub mod structs;
 pub mod subscribe;
+pub mod supervisor;
+pub mod units;
 pub mod utils;
-
+pub mod ws_server;
+
+use crate::backfill::Backfill;
+use crate::commitment::Commitment;
+use crate::config::Config;
+use crate::gap::GapTracker;
+use crate::obv2::ObV2OpenOrdersPlugin;
+use crate::registry::PluginRegistry;
 use crate::structs::{Account, MessageTransaction};
 use crate::subscribe::subscribe_geyser;
 use async_trait::async_trait;
-use obv2::{ObV2BooksPlugin, ObV2EventsPlugin, ObV2TransactionsPlugin};
+use sinks::{ChannelSink, RotatingFileSink, Sink, StdoutSink};
 use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::clock::Clock;
 use std::env;
 use std::sync::Arc;
-use structs::BotMsg;
+use std::time::Instant;
+use structs::{BotMsg, IndicatorMsg};
+use supervisor::Supervisor;
 use tokio::task::JoinHandle;
+use ws_server::{bind_ws_server, run_ws_server};
+
+const CHANNEL_CAPACITY: usize = 4096;
 
 #[async_trait]
 pub trait Extractor: Send + Sync {
@@ -21,11 +46,36 @@ pub trait Extractor: Send + Sync {
 
     fn account(&self) -> String;
 
+    /// Other accounts the extractor reads (e.g. oracles), their updates go to `extract` too
+    fn extra_accounts(&self) -> Vec<String> {
+        vec![]
+    }
+
+    fn commitment(&self) -> Commitment;
+
+    /// Whether the extractor needs cluster time, the `Clock` sysvar is then subscribed once for all
+    fn uses_clock(&self) -> bool {
+        false
+    }
+
+    /// Cluster time at the slot of a `Clock` sysvar update
+    fn update_clock(&mut self, _clock: &Clock) {}
+
     fn extract(&mut self, account: &mut Account) -> anyhow::Result<BotMsg>;
 
+    /// Updates of rolled back slots went out, the surviving account is extracted again next.
+    /// Its output must not build on what clients got from the dropped fork.
+    fn roll_back(&mut self) {}
+
     async fn load(&mut self, client: &RpcClient) -> anyhow::Result<BotMsg>;
+
+    /// Errors of items the last `extract` or `load` skipped, the rest of the update still went out
+    fn take_errors(&mut self) -> Vec<anyhow::Error> {
+        vec![]
+    }
 }
 
+#[async_trait]
 pub trait Parser: Send + Sync {
     fn name(&self) -> String;
 
@@ -33,7 +83,29 @@ pub trait Parser: Send + Sync {
 
     fn account(&self) -> String;
 
+    fn commitment(&self) -> Commitment;
+
     fn parse(&self, transaction: &MessageTransaction) -> anyhow::Result<BotMsg>;
+
+    /// Replay transactions after `from_slot` up to the first streamed slot through rpc
+    async fn backfill(
+        &self,
+        _client: &RpcClient,
+        _from_slot: u64,
+        _to_slot: u64,
+    ) -> anyhow::Result<Backfill> {
+        Ok(Backfill::default())
+    }
+}
+
+/// Consumes other plugins' output and derives new indicators from it
+pub trait Aggregator: Send + Sync {
+    fn name(&self) -> String;
+
+    /// Indicator names this aggregator consumes
+    fn sources(&self) -> Vec<String>;
+
+    fn aggregate(&mut self, msg: &IndicatorMsg) -> anyhow::Result<BotMsg>;
 }
 
 #[tokio::main()]
@@ -42,78 +114,74 @@ async fn main() -> anyhow::Result<()> {
     let rpc_url = env::var("RPC_URL").expect("RPC_URL not set in .env");
     let triton_url = env::var("TRITON_URL").expect("TRITON_URL not set in .env");
     let triton_token = env::var("TRITON_TOKEN").expect("TRITON_TOKEN not set in .env");
+    let config_path = env::var("CONFIG_PATH").unwrap_or("config.toml".to_string());
+    let ws_addr = env::var("WS_ADDR").unwrap_or("0.0.0.0:8080".to_string());
 
     tracing_subscriber::fmt()
         .with_max_level(tracing::Level::INFO)
         .with_file(false)
+        // keep stdout for the jsonl sink
+        .with_writer(std::io::stderr)
         // .with_target(false)
         // .without_time()
         .init();
 
-    let mut parsers: Vec<Box<dyn Parser>> = Vec::new();
-    let mut extractors: Vec<Box<dyn Extractor>> = Vec::new();
-
-    // Bids
-    extractors.push(Box::new(ObV2BooksPlugin {
-        indicator_name: "ob_v2_sol_usdc_bids".to_string(),
-        account: "53v47CBoaKwoM8tSEDN4oNyCc2ZJenDeuhMJTEw7fL2M".to_string(),
-        program_id: "opnb2LAfJYbRMAHHvqjCwQxanZn7ReEHp1k81EohpZb".to_string(),
-        base_decimals: 9,
-        quote_decimals: 6,
-        base_lot_size: 1000000,
-        quote_lot_size: 1,
-    }));
-
-    // Asks
-    extractors.push(Box::new(ObV2BooksPlugin {
-        indicator_name: "ob_v2_sol_usdc_asks".to_string(),
-        account: "Ad5skEiFoaeA27G3UhbpuwnFBCvmuuGEyoiijZhcd5xX".to_string(),
-        program_id: "opnb2LAfJYbRMAHHvqjCwQxanZn7ReEHp1k81EohpZb".to_string(),
-        base_decimals: 9,
-        quote_decimals: 6,
-        base_lot_size: 1000000,
-        quote_lot_size: 1,
-    }));
-
-    // Events (Fill/Cancel)
-    extractors.push(Box::new(ObV2EventsPlugin {
-        indicator_name: "ob_v2_sol_usdc_events".to_string(),
-        account: "F7s6bScqRXB2gsU6s8QHSXJTmpS5t6SfVBs4V2k3HNKn".to_string(),
-        program_id: "opnb2LAfJYbRMAHHvqjCwQxanZn7ReEHp1k81EohpZb".to_string(),
-        base_decimals: 9,
-        quote_decimals: 6,
-        base_lot_size: 1000000,
-        quote_lot_size: 1,
-    }));
-
-    // Transactions (place_order, cancel_order)
-    parsers.push(Box::new(ObV2TransactionsPlugin {
-        indicator_name: "ob_v2_sol_usdc_txs".to_string(),
-        account: "CFSMrBssNG8Ud1edW59jNLnq2cwrQ9uY5cM3wXmqRJj3".to_string(),
-        program_id: "opnb2LAfJYbRMAHHvqjCwQxanZn7ReEHp1k81EohpZb".to_string(),
-        base_decimals: 9,
-        quote_decimals: 6,
-        base_lot_size: 1000000,
-        quote_lot_size: 1,
-    }));
-
-    // subscribe geyser with extractor accounts
+    let mut config = Config::load(&config_path)?;
+    let client = RpcClient::new(rpc_url.clone());
+
+    // Comma separated open orders accounts to track, attached to the market they belong to
+    let oos_keys = env::var("OOS_KEY").unwrap_or_default();
+    for oos_key in oos_keys
+        .split(',')
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+    {
+        let market = ObV2OpenOrdersPlugin::market_of(&client, oos_key).await?;
+        config.attach_open_orders(oos_key, &market)?;
+    }
+    let mut registry = PluginRegistry::from_config(&config, &client).await?;
+    tracing::info!(
+        "Loaded {} markets, {} extractors, {} parsers, {} aggregators",
+        config.markets.len(),
+        registry.extractors.len(),
+        registry.parsers.len(),
+        registry.aggregators.len()
+    );
+
+    // Output sinks, the channel sink feeds websocket clients
+    let channel = ChannelSink::new(CHANNEL_CAPACITY);
+    let listener = bind_ws_server(&ws_addr).await?;
+    tokio::spawn(run_ws_server(listener, channel.sender.clone()));
+
+    let mut sinks: Vec<Box<dyn Sink>> = vec![Box::new(channel)];
+    if config.sinks.stdout {
+        sinks.push(Box::new(StdoutSink::new(config.sinks.stdout_units)?));
+    }
+    if let Some(file) = &config.sinks.file {
+        sinks.push(Box::new(RotatingFileSink::new(
+            &file.path,
+            file.max_bytes,
+            file.max_files,
+            file.units,
+        )?));
+    }
+
+    // subscribe geyser with extractor accounts, plugin state survives reconnects
+    let mut supervisor = Supervisor::new(config.reconnect.clone());
+    let mut tracker = GapTracker::default();
     loop {
-        match subscribe_geyser(
+        let started = Instant::now();
+        let result = subscribe_geyser(
             rpc_url.clone(),
             triton_url.clone(),
             triton_token.clone(),
-            &mut extractors,
-            &parsers,
+            &mut registry,
+            &mut sinks,
+            &mut tracker,
         )
-        .await
-        {
-            Ok(()) => {
-                tracing::info!("Geyser subscribe finished");
-            }
-            Err(e) => {
-                tracing::error!("Subscribe geyser failed: {:?}", e);
-            }
-        };
+        .await;
+
+        let delay = supervisor.next_delay(result, started.elapsed())?;
+        tokio::time::sleep(delay).await;
     }
 }
@@ -1,7 +1,19 @@
 pub mod ob_book;
 pub mod ob_event;
+pub mod ob_fills;
+pub mod ob_instruction;
+pub mod ob_market;
+pub mod ob_market_view;
+pub mod ob_my_fills;
+pub mod ob_open_orders;
 pub mod ob_transaction;
 
 pub use ob_book::*;
 pub use ob_event::*;
+pub use ob_fills::*;
+pub use ob_instruction::*;
+pub use ob_market::*;
+pub use ob_market_view::*;
+pub use ob_my_fills::*;
+pub use ob_open_orders::*;
 pub use ob_transaction::*;
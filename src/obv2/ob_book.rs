@@ -1,25 +1,260 @@
-use bytemuck;
+use std::collections::{BTreeMap, HashMap};
 use std::mem;
 use std::str::FromStr;
-use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::structs::{Account, BotMsg, ObV2BooksData, OpenBook};
-use crate::utils::token_decimals;
+use crate::commitment::Commitment;
+use crate::error::GeyserError;
+use crate::structs::{Account, BotMsg, ObV2BookDiff, ObV2BooksData, ObV2Level, OpenBook};
+use crate::units::MarketUnits;
+use crate::utils::load_account_data;
 use crate::Extractor;
 use anchor_lang::prelude::Pubkey;
 use async_trait::async_trait;
-use openbook_v2::state::{BookSide, Side};
+use openbook_v2::state::{BookSide, BookSideOrderTree, Market, Side};
 use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::clock::Clock;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct ObV2BooksPlugin {
     pub indicator_name: String,
     pub account: String,
     pub program_id: String,
-    pub base_lot_size: u64,
-    pub quote_lot_size: u64,
-    pub base_decimals: u8,
-    pub quote_decimals: u8,
+    pub units: MarketUnits,
+    pub commitment: Commitment,
+    /// Emit a full snapshot every N updates so clients can resync, diffs in between.
+    /// 0 emits snapshots only.
+    pub snapshot_interval: u64,
+    /// Orders of the previous update, keyed by `node.key`
+    pub orders: HashMap<u128, OpenBook>,
+    /// L2 quantity in base lots of the previous update, keyed by price in lots
+    pub levels: BTreeMap<i64, i64>,
+    pub updates: u64,
+    /// Sequence number of the last snapshot or diff sent, kept across reconnects
+    pub seq: u64,
+    /// `seq` of the last snapshot sent
+    pub snapshot_seq: u64,
+    /// Market account, its oracles price pegged orders
+    pub market: String,
+    /// Oracle accounts of the market, `oracle_a` first
+    pub oracles: Vec<String>,
+    /// Leave orders the program skips when matching out of `books`
+    pub skip_invalid: bool,
+    /// Raw `Market` account data, read on load and kept up to date (oracle config)
+    pub market_data: Vec<u8>,
+    /// Last update of each oracle, keyed by pubkey
+    pub oracle_accounts: HashMap<String, Account>,
+    /// Last update of the book side, priced again when an oracle moves
+    pub book: Option<Account>,
+    /// Cluster unix time by slot from the `Clock` sysvar, for slots without block time yet
+    pub clocks: BTreeMap<u64, i64>,
+}
+
+/// Slots of cluster time kept, book updates lag the clock by a few slots at most
+const CLOCK_SLOTS: u64 = 150;
+
+impl ObV2BooksPlugin {
+    fn level(&self, price_lots: i64, quantity: i64) -> ObV2Level {
+        ObV2Level {
+            price_lots,
+            price: self.units.price(price_lots),
+            amount: self.units.base_lots(quantity),
+            amount_lots: quantity,
+            amount_native: self.units.base_lots_native(quantity),
+        }
+    }
+
+    /// Oracle price in lots at `slot`, none until the market and all its oracles are loaded
+    fn oracle_price_lots(&self, slot: u64) -> Option<i64> {
+        if self.oracles.is_empty() || self.market_data.len() < mem::size_of::<Market>() + 8 {
+            return None;
+        }
+        let market =
+            bytemuck::from_bytes::<Market>(&self.market_data[8..mem::size_of::<Market>() + 8]);
+
+        let mut oracles = self.oracles.iter().map(|key| self.oracle_accounts.get(key));
+        let oracle_a = oracles.next().flatten();
+        let oracle_b = oracles.next().flatten();
+        if oracle_a.is_none() || (self.oracles.len() > 1 && oracle_b.is_none()) {
+            return None;
+        }
+
+        match market.oracle_price_lots(oracle_a, oracle_b, slot) {
+            Ok(price_lots) => price_lots,
+            Err(e) => {
+                tracing::warn!("{}: no oracle price: {:?}", self.indicator_name, e);
+                None
+            }
+        }
+    }
+
+    /// Block time of the slot, else the clock of the latest slot up to it.
+    /// Loaded accounts have no slot (0), the latest clock is when they were read.
+    fn cluster_time(&self, slot: u64, block_time: Option<i64>) -> Option<i64> {
+        if block_time.is_some() {
+            return block_time;
+        }
+        let clock = if slot == 0 {
+            self.clocks.last_key_value()
+        } else {
+            self.clocks.range(..=slot).next_back()
+        };
+        clock.map(|(_, unix_timestamp)| *unix_timestamp)
+    }
+
+    /// Book at the cluster time of the update, the program judges expiry with it too
+    fn extract_book(
+        &mut self,
+        account: &Account,
+        slot: u64,
+        block_time: Option<i64>,
+    ) -> anyhow::Result<BotMsg> {
+        let bookside = load_account_data::<BookSide>(account)?;
+
+        let is_buy = match bookside.side() {
+            Side::Ask => false,
+            Side::Bid => true,
+        };
+        let now_ts = self
+            .cluster_time(slot, block_time)
+            .ok_or(GeyserError::NoClusterTime(slot))?
+            .max(0) as u64;
+        let oracle_price_lots = self.oracle_price_lots(slot);
+        let best_price = bookside.best_price(now_ts, oracle_price_lots);
+
+        let mut books: Vec<OpenBook> = vec![];
+        let mut levels: BTreeMap<i64, i64> = BTreeMap::new();
+
+        bookside
+            .iter_all_including_invalid(now_ts, oracle_price_lots)
+            .for_each(|order| {
+                let is_valid = order.is_valid();
+                if is_valid {
+                    *levels.entry(order.price_lots).or_default() += order.node.quantity;
+                } else if self.skip_invalid {
+                    return;
+                }
+                let is_oracle_pegged = order.handle.order_tree == BookSideOrderTree::OraclePegged;
+                // -1 is a pegged order without limit, fixed orders don't use it
+                let peg_limit_lots = Some(order.node.peg_limit)
+                    .filter(|peg_limit| is_oracle_pegged && *peg_limit != -1);
+                books.push(OpenBook {
+                    order_id: order.node.key,
+                    owner: order.node.owner.to_string(),
+                    price: self.units.price(order.price_lots),
+                    price_lots: order.price_lots,
+                    amount: self.units.base_lots(order.node.quantity),
+                    amount_lots: order.node.quantity,
+                    amount_native: self.units.base_lots_native(order.node.quantity),
+                    is_buy,
+                    is_oracle_pegged,
+                    peg_limit: peg_limit_lots.map(|price_lots| self.units.price(price_lots)),
+                    peg_limit_lots,
+                    time_in_force: order.node.time_in_force,
+                    expiry_timestamp: expiry_timestamp(
+                        order.node.timestamp,
+                        order.node.time_in_force,
+                    ),
+                    is_valid,
+                });
+            });
+
+        tracing::info!(
+            "is_buy: {:?}, best_lots: {:?}, books: {:?}",
+            is_buy,
+            best_price,
+            books.len()
+        );
+
+        Ok(self.diff(books, levels, best_price))
+    }
+
+    /// Snapshot or diff of the side against the previous update, none if nothing changed
+    fn diff(
+        &mut self,
+        books: Vec<OpenBook>,
+        levels: BTreeMap<i64, i64>,
+        best_price: Option<i64>,
+    ) -> BotMsg {
+        let best = best_price.map(|price_lots| self.units.price(price_lots));
+
+        let is_snapshot = self.is_snapshot();
+        self.updates += 1;
+
+        // Per order diff against previous update
+        let mut orders: HashMap<u128, OpenBook> = HashMap::with_capacity(books.len());
+        let mut added: Vec<OpenBook> = vec![];
+        let mut changed: Vec<OpenBook> = vec![];
+        for order in books.iter() {
+            match self.orders.remove(&order.order_id) {
+                Some(prev) => {
+                    if prev.amount != order.amount
+                        || prev.price != order.price
+                        || prev.is_valid != order.is_valid
+                    {
+                        changed.push(order.clone());
+                    }
+                }
+                None => added.push(order.clone()),
+            }
+            orders.insert(order.order_id, order.clone());
+        }
+        let removed: Vec<OpenBook> = mem::replace(&mut self.orders, orders)
+            .into_values()
+            .collect();
+
+        // L2 diff, removed levels are sent with zero amount
+        let mut level_diffs: Vec<ObV2Level> = vec![];
+        for (price_lots, quantity) in levels.iter() {
+            if self.levels.get(price_lots) != Some(quantity) {
+                level_diffs.push(self.level(*price_lots, *quantity));
+            }
+        }
+        for price_lots in self.levels.keys() {
+            if !levels.contains_key(price_lots) {
+                level_diffs.push(self.level(*price_lots, 0));
+            }
+        }
+        self.levels = levels;
+
+        if is_snapshot {
+            let levels = self
+                .levels
+                .iter()
+                .map(|(price_lots, quantity)| self.level(*price_lots, *quantity))
+                .collect();
+            self.seq += 1;
+            self.snapshot_seq = self.seq;
+            return BotMsg::ObV2Books(ObV2BooksData {
+                seq: self.seq,
+                best,
+                best_lots: best_price,
+                books,
+                levels,
+            });
+        }
+
+        if added.is_empty() && removed.is_empty() && changed.is_empty() && level_diffs.is_empty() {
+            return BotMsg::Unimplemented;
+        }
+
+        self.seq += 1;
+        BotMsg::ObV2BookDiff(ObV2BookDiff {
+            seq: self.seq,
+            snapshot_seq: self.snapshot_seq,
+            best,
+            best_lots: best_price,
+            added,
+            removed,
+            changed,
+            levels: level_diffs,
+        })
+    }
+
+    fn is_snapshot(&self) -> bool {
+        self.updates == 0
+            || self.snapshot_interval == 0
+            || self.updates % self.snapshot_interval == 0
+    }
 }
 
 #[async_trait]
@@ -35,8 +270,75 @@ impl Extractor for ObV2BooksPlugin {
         self.account.clone()
     }
 
+    fn extra_accounts(&self) -> Vec<String> {
+        let mut accounts = self.oracles.clone();
+        if !self.oracles.is_empty() {
+            accounts.push(self.market.clone());
+        }
+        accounts
+    }
+
+    fn commitment(&self) -> Commitment {
+        self.commitment
+    }
+
+    fn uses_clock(&self) -> bool {
+        true
+    }
+
+    fn roll_back(&mut self) {
+        // Clients applied diffs of the dropped fork, resync them with a snapshot
+        self.updates = 0;
+    }
+
+    fn update_clock(&mut self, clock: &Clock) {
+        self.clocks.insert(clock.slot, clock.unix_timestamp);
+        while let Some(entry) = self.clocks.first_entry() {
+            if *entry.key() + CLOCK_SLOTS >= clock.slot {
+                break;
+            }
+            entry.remove();
+        }
+    }
+
     async fn load(&mut self, client: &RpcClient) -> anyhow::Result<BotMsg> {
-        let account_pubkey = Pubkey::from_str(&self.account).unwrap();
+        // Always start (or resync after reconnect) with a full snapshot
+        self.updates = 0;
+
+        if !self.oracles.is_empty() {
+            let market_pubkey = Pubkey::from_str(&self.market)
+                .map_err(|_| GeyserError::InvalidPubkey(self.market.clone()))?;
+            self.market_data = client.get_account_data(&market_pubkey).await?;
+
+            for oracle in self.oracles.iter() {
+                let oracle_pubkey = Pubkey::from_str(oracle)
+                    .map_err(|_| GeyserError::InvalidPubkey(oracle.clone()))?;
+                match client.get_account(&oracle_pubkey).await {
+                    Ok(account) => {
+                        self.oracle_accounts.insert(
+                            oracle.clone(),
+                            Account {
+                                is_startup: false,
+                                slot: 0,
+                                pubkey: oracle_pubkey,
+                                lamports: account.lamports,
+                                owner: account.owner,
+                                executable: account.executable,
+                                rent_epoch: account.rent_epoch,
+                                data: account.data,
+                                write_version: 0,
+                                txn_signature: String::new(),
+                                block_time: None,
+                            },
+                        );
+                    }
+                    Err(e) => tracing::warn!("{}: oracle {}: {}", self.indicator_name, oracle, e),
+                }
+            }
+        }
+
+        let account_pubkey = Pubkey::from_str(&self.account)
+            .map_err(|_| GeyserError::InvalidPubkey(self.account.clone()))?;
         let account = client.get_account(&account_pubkey).await;
         if account.is_ok() {
             let account = account.unwrap();
@@ -51,6 +353,7 @@ impl Extractor for ObV2BooksPlugin {
                 data: account.data,
                 write_version: 0,
                 txn_signature: String::new(),
+                block_time: None,
             });
         }
 
@@ -58,46 +361,367 @@ impl Extractor for ObV2BooksPlugin {
     }
 
     fn extract(&mut self, account: &mut Account) -> anyhow::Result<BotMsg> {
-        let data = &account.data;
-        let bookside = bytemuck::from_bytes::<BookSide>(&data[8..mem::size_of::<BookSide>() + 8]);
+        let key = account.pubkey.to_string();
+        if key == self.market {
+            self.market_data = account.data.clone();
+            return Ok(BotMsg::Unimplemented);
+        }
+        if self.oracles.contains(&key) {
+            self.oracle_accounts.insert(key, account.clone());
+            // Pegged orders moved with the oracle
+            return match self.book.take() {
+                Some(book) => {
+                    let msg = self.extract_book(&book, account.slot, account.block_time);
+                    self.book = Some(book);
+                    msg
+                }
+                None => Ok(BotMsg::Unimplemented),
+            };
+        }
 
-        let is_buy = match bookside.side() {
-            Side::Ask => false,
-            Side::Bid => true,
+        let msg = self.extract_book(account, account.slot, account.block_time)?;
+        if !self.oracles.is_empty() {
+            self.book = Some(account.clone());
+        }
+        Ok(msg)
+    }
+}
+
+/// Unix time an order placed at `timestamp` expires at, none for orders living until cancelled
+pub fn expiry_timestamp(timestamp: u64, time_in_force: u16) -> Option<u64> {
+    (time_in_force > 0).then(|| timestamp + time_in_force as u64)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::obv2::ob_market_view::ObV2MarketViewPlugin;
+    use crate::structs::IndicatorMsg;
+    use crate::Aggregator;
+    use anchor_lang::Discriminator;
+    use bytemuck::{bytes_of, Zeroable};
+    use openbook_v2::state::{
+        new_node_key, oracle_pegged_price_data, LeafNode, PostOrderType, StubOracle,
+    };
+
+    fn side(snapshot_interval: u64) -> ObV2BooksPlugin {
+        ObV2BooksPlugin {
+            units: MarketUnits {
+                base_lot_size: 1_000_000,
+                quote_lot_size: 1,
+                base_decimals: 9,
+                quote_decimals: 6,
+            },
+            snapshot_interval,
+            ..Default::default()
+        }
+    }
+
+    fn order(plugin: &ObV2BooksPlugin, order_id: u128, price_lots: i64, quantity: i64) -> OpenBook {
+        OpenBook {
+            owner: String::new(),
+            order_id,
+            is_buy: true,
+            price: plugin.units.price(price_lots),
+            price_lots,
+            amount: plugin.units.base_lots(quantity),
+            amount_lots: quantity,
+            amount_native: plugin.units.base_lots_native(quantity),
+            is_oracle_pegged: false,
+            peg_limit: None,
+            peg_limit_lots: None,
+            time_in_force: 0,
+            expiry_timestamp: None,
+            is_valid: true,
+        }
+    }
+
+    /// Update of the side with (order_id, price_lots, quantity) orders
+    fn update(plugin: &mut ObV2BooksPlugin, orders: &[(u128, i64, i64)]) -> BotMsg {
+        let books: Vec<OpenBook> = orders
+            .iter()
+            .map(|(order_id, price_lots, quantity)| {
+                order(plugin, *order_id, *price_lots, *quantity)
+            })
+            .collect();
+        let mut levels: BTreeMap<i64, i64> = BTreeMap::new();
+        for (_, price_lots, quantity) in orders.iter() {
+            *levels.entry(*price_lots).or_default() += quantity;
+        }
+        let best = levels.keys().next_back().copied();
+        plugin.diff(books, levels, best)
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut plugin = side(100);
+
+        let BotMsg::ObV2Books(snapshot) =
+            update(&mut plugin, &[(1, 100, 5), (2, 100, 3), (3, 99, 4)])
+        else {
+            panic!("first update should be a snapshot");
         };
-        let now_ts = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
-        let best_price = bookside.best_price(now_ts, None);
-        let price_factor = token_decimals(self.base_decimals - self.quote_decimals)
-            * self.quote_lot_size as f64
-            / self.base_lot_size as f64;
-        let base_factor = self.base_lot_size as f64 / token_decimals(self.base_decimals);
+        assert_eq!(snapshot.seq, 1);
+        assert_eq!(snapshot.books.len(), 3);
+        assert_eq!(snapshot.levels.len(), 2);
 
-        let mut books: Vec<OpenBook> = vec![];
+        // Order 1 partially filled, 3 cancelled (its level is gone), 4 added on a new level
+        let BotMsg::ObV2BookDiff(diff) =
+            update(&mut plugin, &[(1, 100, 2), (2, 100, 3), (4, 101, 1)])
+        else {
+            panic!("second update should be a diff");
+        };
+        assert_eq!((diff.seq, diff.snapshot_seq), (2, 1));
+        assert_eq!(diff.best_lots, Some(101));
+        assert_eq!(diff.added, vec![order(&plugin, 4, 101, 1)]);
+        assert_eq!(diff.changed, vec![order(&plugin, 1, 100, 2)]);
+        assert_eq!(diff.removed, vec![order(&plugin, 3, 99, 4)]);
 
-        bookside
-            .iter_all_including_invalid(now_ts, None)
-            .for_each(|order| {
-                books.push(OpenBook {
-                    order_id: order.node.key,
-                    owner: order.node.owner.to_string(),
-                    price: (order.price_lots as f64) * price_factor,
-                    amount: (order.node.quantity as f64) * base_factor,
-                    is_buy,
-                });
-            });
+        let level = |price_lots: i64| {
+            diff.levels
+                .iter()
+                .find(|level| level.price_lots == price_lots)
+                .map(|level| level.amount_lots)
+        };
+        assert_eq!(diff.levels.len(), 3);
+        assert_eq!(level(99), Some(0));
+        assert!(diff
+            .levels
+            .iter()
+            .find(|level| level.price_lots == 99)
+            .unwrap()
+            .amount
+            .is_zero());
+        assert_eq!(level(100), Some(5));
+        assert_eq!(level(101), Some(1));
+
+        // Nothing changed, nothing sent and the sequence doesn't move
+        assert_eq!(
+            update(&mut plugin, &[(1, 100, 2), (2, 100, 3), (4, 101, 1)]),
+            BotMsg::Unimplemented
+        );
+        let BotMsg::ObV2BookDiff(diff) = update(&mut plugin, &[(1, 100, 2), (2, 100, 3)]) else {
+            panic!("removal should be a diff");
+        };
+        assert_eq!((diff.seq, diff.snapshot_seq), (3, 1));
+    }
 
-        let best = match best_price {
-            Some(price) => Some((price as f64) * price_factor),
-            None => None,
+    #[test]
+    fn test_snapshot_interval() {
+        let mut plugin = side(3);
+        let kinds: Vec<&str> = (0..7)
+            .map(|i| match update(&mut plugin, &[(1, 100, 10 + i)]) {
+                BotMsg::ObV2Books(_) => "snapshot",
+                BotMsg::ObV2BookDiff(_) => "diff",
+                _ => "none",
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec!["snapshot", "diff", "diff", "snapshot", "diff", "diff", "snapshot"]
+        );
+        assert_eq!((plugin.seq, plugin.snapshot_seq), (7, 7));
+
+        // 0 sends snapshots only
+        let mut plugin = side(0);
+        for i in 0..3 {
+            assert!(matches!(
+                update(&mut plugin, &[(1, 100, 10 + i)]),
+                BotMsg::ObV2Books(_)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_roll_back() {
+        let mut bids = side(100);
+        let mut asks = side(100);
+        let mut view = ObV2MarketViewPlugin {
+            indicator_name: "market".to_string(),
+            bids_name: "bids".to_string(),
+            asks_name: "asks".to_string(),
+            depth: 5,
+            ..Default::default()
+        };
+        let mut aggregate = |name: &str, slot: u64, data: BotMsg| {
+            view.aggregate(&IndicatorMsg::new(name.to_string(), slot, 0, data))
+                .unwrap()
         };
 
-        tracing::info!(
-            "is_buy: {:?}, best: {:?}, books: {:?}",
-            is_buy,
-            best,
-            books.len()
+        aggregate("asks", 100, update(&mut asks, &[(1, 105, 1)]));
+        aggregate("bids", 100, update(&mut bids, &[(2, 100, 1)]));
+        // Order added on a fork
+        let msg = aggregate("bids", 101, update(&mut bids, &[(2, 100, 1), (3, 101, 1)]));
+        assert!(matches!(msg, BotMsg::ObV2Market(view) if view.best_bid_lots == Some(101)));
+
+        // Slot 101 rolled back, the account of slot 100 is extracted again
+        bids.roll_back();
+        let msg = update(&mut bids, &[(2, 100, 1)]);
+        assert!(matches!(msg, BotMsg::ObV2Books(_)));
+        let msg = aggregate("bids", 100, msg);
+        assert!(matches!(msg, BotMsg::ObV2Market(view) if view.best_bid_lots == Some(100)));
+
+        // Diffs on the new fork build on that snapshot
+        let msg = aggregate("bids", 102, update(&mut bids, &[(2, 100, 2)]));
+        let BotMsg::ObV2Market(view) = msg else {
+            panic!("diff after the snapshot should update the view");
+        };
+        assert_eq!(view.bids.len(), 1);
+        assert_eq!(view.bids[0].amount_lots, 2);
+    }
+
+    #[test]
+    fn test_cluster_time() {
+        let mut plugin = side(0);
+        assert_eq!(plugin.cluster_time(100, None), None);
+
+        for slot in [100, 101, 103] {
+            plugin.update_clock(&Clock {
+                slot,
+                unix_timestamp: 1_700_000_000 + slot as i64,
+                ..Default::default()
+            });
+        }
+        // Block time first, else the clock of the slot or the closest one before it
+        assert_eq!(
+            plugin.cluster_time(101, Some(1_600_000_000)),
+            Some(1_600_000_000)
         );
+        assert_eq!(plugin.cluster_time(101, None), Some(1_700_000_101));
+        assert_eq!(plugin.cluster_time(102, None), Some(1_700_000_101));
+        assert_eq!(plugin.cluster_time(99, None), None);
+        assert_eq!(plugin.cluster_time(0, None), Some(1_700_000_103));
+
+        plugin.update_clock(&Clock {
+            slot: 101 + CLOCK_SLOTS,
+            ..Default::default()
+        });
+        assert_eq!(plugin.clocks.keys().next(), Some(&101));
+    }
+
+    #[test]
+    fn test_expiry_timestamp() {
+        assert_eq!(expiry_timestamp(1_700_000_000, 0), None);
+        assert_eq!(expiry_timestamp(1_700_000_000, 60), Some(1_700_000_060));
+        assert_eq!(expiry_timestamp(0, u16::MAX), Some(u16::MAX as u64));
+    }
+
+    fn account(pubkey: Pubkey, owner: Pubkey, data: Vec<u8>) -> Account {
+        Account {
+            is_startup: false,
+            slot: 10,
+            pubkey,
+            lamports: 0,
+            owner,
+            executable: false,
+            rent_epoch: 0,
+            data,
+            write_version: 1,
+            txn_signature: String::new(),
+            block_time: Some(1_700_000_000),
+        }
+    }
 
-        Ok(BotMsg::ObV2Books(ObV2BooksData { best, books }))
+    /// Bids with two orders pegged to the oracle: 5 lots under it, and at it with a
+    /// peg limit of 90 lots
+    fn pegged_bids() -> Vec<u8> {
+        let mut bookside = BookSide::zeroed();
+        for (seq_num, offset, peg_limit) in [(1, -5, -1), (2, 0, 90)] {
+            let key = new_node_key(Side::Bid, oracle_pegged_price_data(offset), seq_num);
+            let leaf = LeafNode::new(
+                0,
+                key,
+                Pubkey::new_unique(),
+                3,
+                1_700_000_000,
+                PostOrderType::Limit,
+                0,
+                peg_limit,
+                seq_num,
+            );
+            bookside
+                .insert_leaf(BookSideOrderTree::OraclePegged, &leaf)
+                .unwrap();
+        }
+        [vec![0; 8], bytes_of(&bookside).to_vec()].concat()
+    }
+
+    #[test]
+    fn test_pegged_orders() {
+        for skip_invalid in [false, true] {
+            let market = Pubkey::new_unique();
+            let oracle = Pubkey::new_unique();
+            let mut plugin = ObV2BooksPlugin {
+                indicator_name: "bids".to_string(),
+                units: MarketUnits {
+                    base_lot_size: 1,
+                    quote_lot_size: 1,
+                    base_decimals: 6,
+                    quote_decimals: 6,
+                },
+                market: market.to_string(),
+                oracles: vec![oracle.to_string()],
+                skip_invalid,
+                ..Default::default()
+            };
+            assert!(plugin.extra_accounts().contains(&market.to_string()));
+
+            // Market and oracle come through the stream, not only on load
+            let mut market_state = Market::zeroed();
+            market_state.oracle_a = Some(oracle).into();
+            market_state.oracle_config.conf_filter = 0.1;
+            market_state.oracle_config.max_staleness_slots = -1;
+            market_state.base_lot_size = 1;
+            market_state.quote_lot_size = 1;
+            market_state.base_decimals = 6;
+            market_state.quote_decimals = 6;
+            let data = [
+                Market::DISCRIMINATOR.to_vec(),
+                bytes_of(&market_state).to_vec(),
+            ]
+            .concat();
+            let msg = plugin.extract(&mut account(market, openbook_v2::ID, data));
+            assert_eq!(msg.unwrap(), BotMsg::Unimplemented);
+
+            // Fake oracle at 100 lots
+            let mut stub = StubOracle::zeroed();
+            stub.price = 100.0;
+            stub.last_update_slot = 10;
+            let data = [StubOracle::DISCRIMINATOR.to_vec(), bytes_of(&stub).to_vec()].concat();
+            let msg = plugin.extract(&mut account(oracle, openbook_v2::ID, data));
+            assert_eq!(msg.unwrap(), BotMsg::Unimplemented);
+
+            let bids = account(Pubkey::new_unique(), openbook_v2::ID, pegged_bids());
+            let BotMsg::ObV2Books(books) = plugin.extract(&mut bids.clone()).unwrap() else {
+                panic!("expected a snapshot");
+            };
+
+            assert_eq!(books.best_lots, Some(95));
+            assert_eq!(books.levels.len(), 1);
+            assert_eq!(books.levels[0].price_lots, 95);
+            assert!(books.books.iter().all(|order| order.is_oracle_pegged));
+            let prices = books
+                .books
+                .iter()
+                .map(|order| (order.price_lots, order.is_valid, order.peg_limit_lots))
+                .collect::<Vec<_>>();
+            if skip_invalid {
+                assert_eq!(prices, vec![(95, true, None)]);
+            } else {
+                // At the oracle is over the peg limit, listed but invalid and out of the levels
+                assert_eq!(prices, vec![(100, false, Some(90)), (95, true, None)]);
+            }
+
+            // The oracle moves, pegged orders move with it
+            stub.price = 110.0;
+            let data = [StubOracle::DISCRIMINATOR.to_vec(), bytes_of(&stub).to_vec()].concat();
+            let BotMsg::ObV2BookDiff(diff) = plugin
+                .extract(&mut account(oracle, openbook_v2::ID, data))
+                .unwrap()
+            else {
+                panic!("expected a diff");
+            };
+            assert_eq!(diff.best_lots, Some(105));
+        }
     }
 }
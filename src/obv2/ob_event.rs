@@ -1,25 +1,33 @@
-use bytemuck::{self, cast_ref};
-use std::mem;
+use bytemuck::cast_ref;
+use std::collections::HashSet;
 use std::str::FromStr;
-use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::structs::{Account, BotMsg, ObV2Cancel, ObV2Event, ObV2Fill};
-use crate::utils::{is_buy, token_decimals};
+use crate::commitment::Commitment;
+use crate::error::GeyserError;
+use crate::structs::{Account, BotMsg, ObV2Cancel, ObV2Envelope, ObV2Event, ObV2Fill};
+use crate::units::{fee_native, Fee, MarketUnits};
+use crate::utils::{is_buy, load_account_data};
 use crate::Extractor;
 use anchor_lang::prelude::Pubkey;
 use async_trait::async_trait;
-use openbook_v2::state::{BookSide, EventHeap, EventType, FillEvent, OutEvent, Side};
+use openbook_v2::state::{EventHeap, EventType, FillEvent, OutEvent};
 use solana_client::nonblocking::rpc_client::RpcClient;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct ObV2EventsPlugin {
     pub indicator_name: String,
     pub account: String,
     pub program_id: String,
-    pub base_lot_size: u64,
-    pub quote_lot_size: u64,
-    pub base_decimals: u8,
-    pub quote_decimals: u8,
+    pub units: MarketUnits,
+    pub commitment: Commitment,
+    /// Fee rates of the market, fill events don't carry the fees paid
+    pub maker_fee: i64,
+    pub taker_fee: i64,
+    /// Heap entries (event type, sequence number) of the previous update.
+    /// Entries stay until consumed, only new ones are emitted.
+    pub seen: HashSet<(u8, u64)>,
+    /// Event types of heap nodes the last update couldn't decode
+    pub unknown_event_types: Vec<u8>,
 }
 
 #[async_trait]
@@ -35,8 +43,13 @@ impl Extractor for ObV2EventsPlugin {
         self.account.clone()
     }
 
+    fn commitment(&self) -> Commitment {
+        self.commitment
+    }
+
     async fn load(&mut self, client: &RpcClient) -> anyhow::Result<BotMsg> {
-        let account_pubkey = Pubkey::from_str(&self.account).unwrap();
+        let account_pubkey = Pubkey::from_str(&self.account)
+            .map_err(|_| GeyserError::InvalidPubkey(self.account.clone()))?;
         let account = client.get_account(&account_pubkey).await;
         if account.is_ok() {
             let account = account.unwrap();
@@ -51,60 +64,193 @@ impl Extractor for ObV2EventsPlugin {
                 data: account.data,
                 write_version: 0,
                 txn_signature: String::new(),
+                block_time: None,
             });
         }
 
         Ok(BotMsg::Unimplemented)
     }
 
+    fn take_errors(&mut self) -> Vec<anyhow::Error> {
+        self.unknown_event_types
+            .drain(..)
+            .map(|event_type| GeyserError::UnknownEventType(event_type).into())
+            .collect()
+    }
+
     fn extract(&mut self, account: &mut Account) -> anyhow::Result<BotMsg> {
-        let data = &account.data;
-        let event_heap =
-            bytemuck::from_bytes::<EventHeap>(&data[8..mem::size_of::<EventHeap>() + 8]);
+        let event_heap = load_account_data::<EventHeap>(account)?;
 
         let mut events: Vec<ObV2Event> = vec![];
 
-        let price_factor = token_decimals(self.base_decimals - self.quote_decimals)
-            * self.quote_lot_size as f64
-            / self.base_lot_size as f64;
-        let base_factor = self.base_lot_size as f64 / token_decimals(self.base_decimals);
-
-        for node in event_heap.nodes {
-            if node.is_free() {
+        let mut seen: HashSet<(u8, u64)> = HashSet::new();
+        // From the head of the heap along the used nodes, the order events were pushed in
+        for (event, _) in event_heap.iter() {
+            let Ok(event_type) = EventType::try_from(event.event_type) else {
+                self.unknown_event_types.push(event.event_type);
                 continue;
-            }
-
-            let event = node.event;
-
-            match EventType::try_from(event.event_type).unwrap() {
+            };
+            match event_type {
                 EventType::Fill => {
-                    let fill: &FillEvent = cast_ref(&event);
+                    let fill: &FillEvent = cast_ref(event);
+                    seen.insert((event.event_type, fill.market_seq_num));
+                    if self.seen.contains(&(event.event_type, fill.market_seq_num)) {
+                        continue;
+                    }
+
+                    let quote_native = self.units.fill_quote_native(fill.price, fill.quantity);
+                    let maker_fee = fee_native(quote_native, self.maker_fee, false);
+                    let taker_fee = fee_native(quote_native, self.taker_fee, true);
                     events.push(ObV2Event::Fill(ObV2Fill {
                         is_buy: is_buy(fill.taker_side()),
                         taker: fill.taker.to_string(),
                         maker: fill.maker.to_string(),
                         order_id: fill.maker_client_order_id,
-                        price: (fill.price as f64) * price_factor,
-                        amount: fill.quantity as f64 * base_factor,
+                        taker_client_order_id: fill.taker_client_order_id,
+                        maker_fee: Fee::from_native(maker_fee as i128, self.units.quote_decimals),
+                        maker_fee_native: maker_fee,
+                        taker_fee: Fee::from_native(taker_fee as i128, self.units.quote_decimals),
+                        taker_fee_native: taker_fee,
+                        seq_num: fill.market_seq_num,
+                        price: self.units.price(fill.price),
+                        price_lots: fill.price,
+                        amount: self.units.base_lots(fill.quantity),
+                        amount_lots: fill.quantity,
+                        amount_native: self.units.base_lots_native(fill.quantity),
                     }));
                 }
                 EventType::Out => {
-                    let out: &OutEvent = cast_ref(&event);
+                    let out: &OutEvent = cast_ref(event);
+                    seen.insert((event.event_type, out.seq_num));
+                    if self.seen.contains(&(event.event_type, out.seq_num)) {
+                        continue;
+                    }
+
                     events.push(ObV2Event::Cancel(ObV2Cancel {
                         is_buy: is_buy(out.side()),
                         owner: out.owner.to_string(),
                         seq_num: out.seq_num,
-                        amount: out.quantity as f64 * base_factor,
+                        amount: self.units.base_lots(out.quantity),
+                        amount_lots: out.quantity,
+                        amount_native: self.units.base_lots_native(out.quantity),
                     }));
                 }
             }
         }
 
-        if events.len() > 0 {
-            tracing::info!("total events: {:?}", events.len());
+        self.seen = seen;
+
+        if !events.is_empty() {
+            tracing::info!("new events: {:?}", events.len());
+
+            // Heap events have no instruction, the signature is the last writer of the heap
+            let signature = Some(account.txn_signature.clone()).filter(|s| !s.is_empty());
+            let events = events
+                .into_iter()
+                .enumerate()
+                .map(|(seq, event)| ObV2Envelope {
+                    slot: account.slot,
+                    block_time: account.block_time,
+                    signature: signature.clone(),
+                    tx_index: None,
+                    ix_index: None,
+                    inner_ix_index: None,
+                    seq: seq as u32,
+                    event,
+                })
+                .collect();
             Ok(BotMsg::ObV2Events(events))
         } else {
             Ok(BotMsg::Unimplemented)
         }
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use bytemuck::{bytes_of, cast, Zeroable};
+    use openbook_v2::state::{AnyEvent, Side};
+
+    #[test]
+    fn test_unknown_event_type() {
+        let owner = Pubkey::new_unique();
+        let mut heap = EventHeap::zeroed();
+        heap.init();
+        heap.push_back(cast(OutEvent::new(Side::Bid, 0, 0, 7, owner, 3)));
+        let mut unknown = AnyEvent::zeroed();
+        unknown.event_type = 9;
+        heap.push_back(unknown);
+
+        let mut data = vec![0u8; 8];
+        data.extend_from_slice(bytes_of(&heap));
+        let mut account = Account {
+            is_startup: false,
+            slot: 10,
+            pubkey: Pubkey::new_unique(),
+            lamports: 0,
+            owner: Pubkey::default(),
+            executable: false,
+            rent_epoch: 0,
+            data,
+            write_version: 1,
+            txn_signature: String::new(),
+            block_time: None,
+        };
+
+        // The unknown node is skipped, the rest of the heap still goes out
+        let mut plugin = ObV2EventsPlugin::default();
+        let BotMsg::ObV2Events(events) = plugin.extract(&mut account).unwrap() else {
+            panic!("expected events");
+        };
+        assert_eq!(events.len(), 1);
+        assert!(matches!(&events[0].event, ObV2Event::Cancel(cancel) if cancel.seq_num == 7));
+
+        let errors = plugin.take_errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "unknown event type 9");
+        assert!(plugin.take_errors().is_empty());
+    }
+
+    #[test]
+    fn test_heap_order() {
+        let owner = Pubkey::new_unique();
+        let out = |seq_num: u64| cast(OutEvent::new(Side::Bid, 0, 0, seq_num, owner, 3));
+        let mut heap = EventHeap::zeroed();
+        heap.init();
+        heap.push_back(out(1));
+        heap.push_back(out(2));
+        // The consumed node is reused, the newest event sits before the older one
+        heap.pop_front().unwrap();
+        heap.push_back(out(3));
+
+        let mut data = vec![0u8; 8];
+        data.extend_from_slice(bytes_of(&heap));
+        let mut account = Account {
+            is_startup: false,
+            slot: 10,
+            pubkey: Pubkey::new_unique(),
+            lamports: 0,
+            owner: Pubkey::default(),
+            executable: false,
+            rent_epoch: 0,
+            data,
+            write_version: 1,
+            txn_signature: String::new(),
+            block_time: None,
+        };
+
+        let mut plugin = ObV2EventsPlugin::default();
+        let BotMsg::ObV2Events(events) = plugin.extract(&mut account).unwrap() else {
+            panic!("expected events");
+        };
+        let seq_nums = events
+            .iter()
+            .map(|envelope| match &envelope.event {
+                ObV2Event::Cancel(cancel) => cancel.seq_num,
+                event => panic!("unexpected event {:?}", event),
+            })
+            .collect::<Vec<u64>>();
+        assert_eq!(seq_nums, vec![2, 3]);
+    }
+}
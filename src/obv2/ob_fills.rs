@@ -0,0 +1,241 @@
+use std::collections::{BTreeMap, HashSet};
+
+use crate::structs::{BotMsg, IndicatorMsg, ObV2Envelope, ObV2Event, ObV2Fill};
+use crate::units::Quantity;
+use crate::Aggregator;
+
+/// Slots a fill key is remembered for
+const DEDUPE_SLOTS: u64 = 300;
+
+/// Identity of a fill, the same for its `FillEvent` on the heap and its `FillLog`.
+///
+/// Narrower than (market, maker, seq, quantity, slot): the slot isn't part of it, a
+/// loaded heap has none and a fill's log can land in a later slot than the heap write
+/// showing it. The market sequence number alone tells fills of a market apart, it is
+/// bumped for every fill, so two keys are only equal for the same fill. The slot only
+/// decides how long a key is remembered, see `ObV2FillDedupe`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ObV2FillKey {
+    pub market: String,
+    pub maker: String,
+    /// Market sequence number of the fill
+    pub seq_num: u64,
+    /// UI amount, exact so both sources agree on it
+    pub amount: Quantity,
+}
+
+/// Fills already published, so a fill seen by both the events and
+/// transactions plugins is only let through once.
+///
+/// Keys are forgotten `DEDUPE_SLOTS` after the slot they were first seen at. Fills of a
+/// heap loaded at startup come with slot 0, they are moved to the first real slot seen
+/// so they age from there instead of being forgotten right away.
+#[derive(Clone, Debug, Default)]
+pub struct ObV2FillDedupe {
+    pub seen: HashSet<ObV2FillKey>,
+    /// Keys by the slot they were first seen at, to forget them
+    pub slots: BTreeMap<u64, Vec<ObV2FillKey>>,
+}
+
+impl ObV2FillDedupe {
+    /// True the first time a fill is seen
+    pub fn insert(&mut self, market: &str, slot: u64, fill: &ObV2Fill) -> bool {
+        // Fills of a loaded heap have slot 0, they age from the first slot seen after
+        if slot > 0 {
+            if let Some(loaded) = self.slots.remove(&0) {
+                self.slots.entry(slot).or_default().extend(loaded);
+            }
+        }
+
+        let key = ObV2FillKey {
+            market: market.to_string(),
+            maker: fill.maker.clone(),
+            seq_num: fill.seq_num,
+            amount: fill.amount,
+        };
+        let is_new = self.seen.insert(key.clone());
+        if is_new {
+            self.slots.entry(slot).or_default().push(key);
+        }
+
+        // Forget old slots
+        let last_slot = self.slots.keys().next_back().copied().unwrap_or_default();
+        while let Some(entry) = self.slots.first_entry() {
+            if *entry.key() + DEDUPE_SLOTS >= last_slot {
+                break;
+            }
+            for key in entry.remove() {
+                self.seen.remove(&key);
+            }
+        }
+
+        is_new
+    }
+
+    /// New fills of an events or transaction message
+    pub fn new_fills<'a>(
+        &mut self,
+        market: &str,
+        data: &'a BotMsg,
+    ) -> Vec<&'a ObV2Envelope<ObV2Event>> {
+        let events = match data {
+            BotMsg::ObV2Events(events) => events,
+            BotMsg::ObV2Transaction(transaction) => &transaction.events,
+            _ => return vec![],
+        };
+
+        events
+            .iter()
+            .filter(|envelope| match &envelope.event {
+                ObV2Event::Fill(fill) => self.insert(market, envelope.slot, fill),
+                _ => false,
+            })
+            .collect()
+    }
+}
+
+/// Public trade feed of one market, each fill exactly once whether it was seen
+/// on the event heap, in a fill log, or both
+#[derive(Clone, Debug, Default)]
+pub struct ObV2FillsPlugin {
+    pub indicator_name: String,
+    pub market: String,
+    /// Events and/or transactions indicator names of the market
+    pub sources: Vec<String>,
+    pub dedupe: ObV2FillDedupe,
+}
+
+impl Aggregator for ObV2FillsPlugin {
+    fn name(&self) -> String {
+        self.indicator_name.clone()
+    }
+
+    fn sources(&self) -> Vec<String> {
+        self.sources.clone()
+    }
+
+    fn aggregate(&mut self, msg: &IndicatorMsg) -> anyhow::Result<BotMsg> {
+        let fills = self
+            .dedupe
+            .new_fills(&self.market, &msg.data)
+            .into_iter()
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if fills.is_empty() {
+            return Ok(BotMsg::Unimplemented);
+        }
+
+        Ok(BotMsg::ObV2Events(fills))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::structs::ObV2TransactionData;
+
+    fn fill(seq_num: u64, amount: &str) -> ObV2Fill {
+        ObV2Fill {
+            taker: "taker".to_string(),
+            maker: "maker".to_string(),
+            is_buy: true,
+            price: "100".parse().unwrap(),
+            price_lots: 100_000,
+            amount: amount.parse().unwrap(),
+            amount_lots: 0,
+            amount_native: 0,
+            order_id: 1,
+            taker_client_order_id: 2,
+            maker_fee: Default::default(),
+            maker_fee_native: 0,
+            taker_fee: Default::default(),
+            taker_fee_native: 0,
+            seq_num,
+        }
+    }
+
+    fn envelope(slot: u64, event: ObV2Event) -> ObV2Envelope<ObV2Event> {
+        ObV2Envelope {
+            slot,
+            block_time: None,
+            signature: None,
+            tx_index: None,
+            ix_index: None,
+            inner_ix_index: None,
+            seq: 0,
+            event,
+        }
+    }
+
+    #[test]
+    fn test_fill_seen_by_both_sources() {
+        let mut dedupe = ObV2FillDedupe::default();
+
+        let heap = BotMsg::ObV2Events(vec![
+            envelope(10, ObV2Event::Fill(fill(1, "0.5"))),
+            envelope(10, ObV2Event::Fill(fill(2, "0.5"))),
+        ]);
+        let logs = BotMsg::ObV2Transaction(ObV2TransactionData {
+            signature: "sig".to_string(),
+            instructions: vec![],
+            events: vec![
+                envelope(10, ObV2Event::Fill(fill(2, "0.5"))),
+                envelope(10, ObV2Event::Fill(fill(3, "0.5"))),
+            ],
+            is_log_truncated: false,
+        });
+
+        assert_eq!(dedupe.new_fills("market", &heap).len(), 2);
+        let fills = dedupe.new_fills("market", &logs);
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].event, ObV2Event::Fill(fill(3, "0.5")));
+        assert!(dedupe.new_fills("market", &heap).is_empty());
+    }
+
+    #[test]
+    fn test_old_slots_forgotten() {
+        let mut dedupe = ObV2FillDedupe::default();
+
+        assert!(dedupe.insert("market", 10, &fill(1, "0.5")));
+        assert!(!dedupe.insert("market", 10, &fill(1, "0.5")));
+        assert!(dedupe.insert("market", 10 + DEDUPE_SLOTS + 1, &fill(2, "0.5")));
+        assert_eq!(dedupe.seen.len(), 1);
+        assert_eq!(dedupe.slots.len(), 1);
+        assert!(dedupe.insert("market", 10 + DEDUPE_SLOTS + 2, &fill(1, "0.5")));
+    }
+
+    #[test]
+    fn test_loaded_fill_then_log() {
+        let mut dedupe = ObV2FillDedupe::default();
+
+        // Heap read at startup, no slot
+        let loaded = BotMsg::ObV2Events(vec![envelope(0, ObV2Event::Fill(fill(1, "0.5")))]);
+        assert_eq!(dedupe.new_fills("market", &loaded).len(), 1);
+
+        // Its log comes much later, after another fill
+        assert!(dedupe.insert("market", 250_000_000, &fill(2, "0.5")));
+        let logs = BotMsg::ObV2Transaction(ObV2TransactionData {
+            signature: "sig".to_string(),
+            instructions: vec![],
+            events: vec![envelope(250_000_000, ObV2Event::Fill(fill(1, "0.5")))],
+            is_log_truncated: false,
+        });
+        assert!(dedupe.new_fills("market", &logs).is_empty());
+    }
+
+    #[test]
+    fn test_distinct_fills_sharing_maker_seq_amount() {
+        let mut dedupe = ObV2FillDedupe::default();
+
+        // Same maker, sequence number and amount on two markets, a few slots apart
+        assert!(dedupe.insert("market", 10, &fill(1, "0.5")));
+        assert!(dedupe.insert("other market", 12, &fill(1, "0.5")));
+        // Fills of one market differ in sequence number, whatever the maker and amount
+        assert!(dedupe.insert("market", 12, &fill(2, "0.5")));
+
+        assert!(!dedupe.insert("market", 20, &fill(1, "0.5")));
+        assert!(!dedupe.insert("other market", 20, &fill(1, "0.5")));
+        assert_eq!(dedupe.seen.len(), 3);
+    }
+}
@@ -0,0 +1,208 @@
+use anchor_lang::AnchorDeserialize;
+use anchor_lang::Discriminator;
+use openbook_v2::instruction::{
+    CancelAllAndPlaceOrders, CancelOrder, ConsumeEvents, PlaceOrder, PlaceOrders, PlaceTakeOrder,
+    SettleFunds,
+};
+use openbook_v2::state::PlaceMultipleOrdersArgs;
+
+use crate::error::GeyserError;
+use crate::structs::{
+    ObV2AccountMeta, ObV2InstructionKind, ObV2MultipleOrdersArgs, ObV2OrderArgs, ParsedInstruction,
+};
+use crate::units::{MarketUnits, Quantity};
+use crate::utils::is_buy;
+
+// Account layouts of the openbook v2 instructions, in order.
+// Unset optional accounts are passed as the program id, so positions are stable.
+const PLACE_ORDER_ACCOUNTS: &[&str] = &[
+    "signer",
+    "open_orders_account",
+    "open_orders_admin",
+    "user_token_account",
+    "market",
+    "bids",
+    "asks",
+    "event_heap",
+    "market_vault",
+    "oracle_a",
+    "oracle_b",
+    "token_program",
+];
+const CANCEL_ORDER_ACCOUNTS: &[&str] = &["signer", "open_orders_account", "market", "bids", "asks"];
+const CANCEL_ALL_AND_PLACE_ORDERS_ACCOUNTS: &[&str] = &[
+    "signer",
+    "open_orders_account",
+    "open_orders_admin",
+    "user_quote_account",
+    "user_base_account",
+    "market",
+    "bids",
+    "asks",
+    "event_heap",
+    "market_quote_vault",
+    "market_base_vault",
+    "oracle_a",
+    "oracle_b",
+    "token_program",
+];
+const PLACE_TAKE_ORDER_ACCOUNTS: &[&str] = &[
+    "signer",
+    "penalty_payer",
+    "market",
+    "market_authority",
+    "bids",
+    "asks",
+    "market_base_vault",
+    "market_quote_vault",
+    "event_heap",
+    "user_base_account",
+    "user_quote_account",
+    "oracle_a",
+    "oracle_b",
+    "token_program",
+    "system_program",
+    "open_orders_admin",
+];
+const SETTLE_FUNDS_ACCOUNTS: &[&str] = &[
+    "owner",
+    "penalty_payer",
+    "open_orders_account",
+    "market",
+    "market_authority",
+    "market_base_vault",
+    "market_quote_vault",
+    "user_base_account",
+    "user_quote_account",
+    "referrer_account",
+    "token_program",
+    "system_program",
+];
+const CONSUME_EVENTS_ACCOUNTS: &[&str] = &["consume_events_admin", "market", "event_heap"];
+
+/// Decode an openbook v2 instruction with its arguments, prices and sizes in UI units
+/// next to raw lots. Unsupported instructions are returned as `Unknown` with their discriminator.
+pub fn decode_instruction(data: &[u8], units: &MarketUnits) -> anyhow::Result<ObV2InstructionKind> {
+    if data.len() < 8 {
+        return Err(GeyserError::InvalidProgramData(format!("instruction data {:?}", data)).into());
+    }
+
+    let discriminator = &data[0..8];
+    let args = &mut &data[8..];
+    let kind = if discriminator == PlaceOrder::DISCRIMINATOR {
+        let ix = PlaceOrder::deserialize(args)?;
+        ObV2InstructionKind::PlaceOrder(ObV2OrderArgs {
+            is_buy: is_buy(ix.args.side),
+            price_lots: ix.args.price_lots,
+            price: units.price(ix.args.price_lots),
+            max_base_lots: ix.args.max_base_lots,
+            amount: units.base_lots(ix.args.max_base_lots),
+            max_quote_lots_including_fees: ix.args.max_quote_lots_including_fees,
+            client_order_id: ix.args.client_order_id,
+            order_type: format!("{:?}", ix.args.order_type),
+            expiry_timestamp: ix.args.expiry_timestamp,
+        })
+    } else if discriminator == PlaceTakeOrder::DISCRIMINATOR {
+        let ix = PlaceTakeOrder::deserialize(args)?;
+        ObV2InstructionKind::PlaceTakeOrder(ObV2OrderArgs {
+            is_buy: is_buy(ix.args.side),
+            price_lots: ix.args.price_lots,
+            price: units.price(ix.args.price_lots),
+            max_base_lots: ix.args.max_base_lots,
+            amount: units.base_lots(ix.args.max_base_lots),
+            max_quote_lots_including_fees: ix.args.max_quote_lots_including_fees,
+            client_order_id: 0,
+            order_type: format!("{:?}", ix.args.order_type),
+            expiry_timestamp: 0,
+        })
+    } else if discriminator == PlaceOrders::DISCRIMINATOR {
+        let ix = PlaceOrders::deserialize(args)?;
+        ObV2InstructionKind::PlaceOrders(ObV2MultipleOrdersArgs {
+            order_type: format!("{:?}", ix.orders_type),
+            bids: multiple_orders(&ix.bids, true, units),
+            asks: multiple_orders(&ix.asks, false, units),
+            limit: ix.limit,
+        })
+    } else if discriminator == CancelAllAndPlaceOrders::DISCRIMINATOR {
+        let ix = CancelAllAndPlaceOrders::deserialize(args)?;
+        ObV2InstructionKind::CancelAllAndPlaceOrders(ObV2MultipleOrdersArgs {
+            order_type: format!("{:?}", ix.orders_type),
+            bids: multiple_orders(&ix.bids, true, units),
+            asks: multiple_orders(&ix.asks, false, units),
+            limit: ix.limit,
+        })
+    } else if discriminator == CancelOrder::DISCRIMINATOR {
+        let ix = CancelOrder::deserialize(args)?;
+        ObV2InstructionKind::CancelOrder {
+            order_id: ix.order_id,
+        }
+    } else if discriminator == SettleFunds::DISCRIMINATOR {
+        ObV2InstructionKind::SettleFunds
+    } else if discriminator == ConsumeEvents::DISCRIMINATOR {
+        let ix = ConsumeEvents::deserialize(args)?;
+        ObV2InstructionKind::ConsumeEvents {
+            limit: ix.limit as u64,
+        }
+    } else {
+        ObV2InstructionKind::Unknown {
+            discriminator: discriminator.to_vec(),
+        }
+    };
+
+    Ok(kind)
+}
+
+/// Resolve instruction accounts to (name, pubkey), extra accounts are named `remaining`
+pub fn resolve_accounts(
+    kind: &ObV2InstructionKind,
+    ix: &ParsedInstruction,
+    accounts: &[String],
+) -> Result<Vec<ObV2AccountMeta>, GeyserError> {
+    let names = match kind {
+        ObV2InstructionKind::PlaceOrder(_) => PLACE_ORDER_ACCOUNTS,
+        ObV2InstructionKind::PlaceTakeOrder(_) => PLACE_TAKE_ORDER_ACCOUNTS,
+        ObV2InstructionKind::PlaceOrders(_) | ObV2InstructionKind::CancelAllAndPlaceOrders(_) => {
+            CANCEL_ALL_AND_PLACE_ORDERS_ACCOUNTS
+        }
+        ObV2InstructionKind::CancelOrder { .. } => CANCEL_ORDER_ACCOUNTS,
+        ObV2InstructionKind::SettleFunds => SETTLE_FUNDS_ACCOUNTS,
+        ObV2InstructionKind::ConsumeEvents { .. } => CONSUME_EVENTS_ACCOUNTS,
+        ObV2InstructionKind::Unknown { .. } => &[],
+    };
+
+    ix.accounts
+        .iter()
+        .enumerate()
+        .map(|(position, index)| {
+            let pubkey = accounts
+                .get(*index as usize)
+                .ok_or(GeyserError::AccountIndex(*index as usize))?;
+            Ok(ObV2AccountMeta {
+                name: names.get(position).unwrap_or(&"remaining").to_string(),
+                pubkey: pubkey.clone(),
+            })
+        })
+        .collect()
+}
+
+fn multiple_orders(
+    orders: &[PlaceMultipleOrdersArgs],
+    is_buy: bool,
+    units: &MarketUnits,
+) -> Vec<ObV2OrderArgs> {
+    orders
+        .iter()
+        .map(|order| ObV2OrderArgs {
+            is_buy,
+            price_lots: order.price_lots,
+            price: units.price(order.price_lots),
+            // Base size is derived on-chain from the quote size
+            max_base_lots: 0,
+            amount: Quantity::default(),
+            max_quote_lots_including_fees: order.max_quote_lots_including_fees,
+            client_order_id: 0,
+            order_type: String::new(),
+            expiry_timestamp: order.expiry_timestamp,
+        })
+        .collect()
+}
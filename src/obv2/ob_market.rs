@@ -0,0 +1,95 @@
+use anchor_lang::Discriminator;
+use bytemuck;
+use std::mem;
+use std::str::FromStr;
+
+use anchor_lang::prelude::Pubkey;
+use openbook_v2::state::Market;
+use solana_client::nonblocking::rpc_client::RpcClient;
+
+use crate::units::MarketUnits;
+
+/// Market parameters as stored in the on-chain `openbook_v2::state::Market` account
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObV2MarketInfo {
+    pub market: String,
+    pub name: String,
+    pub program_id: String,
+    pub bids: String,
+    pub asks: String,
+    pub event_heap: String,
+    pub base_mint: String,
+    pub quote_mint: String,
+    pub base_decimals: u8,
+    pub quote_decimals: u8,
+    pub base_lot_size: u64,
+    pub quote_lot_size: u64,
+    /// Fee rates scaled by 1e6, a negative maker fee is a rebate
+    pub maker_fee: i64,
+    pub taker_fee: i64,
+    /// Oracles pegged orders follow, `oracle_b` is only set next to `oracle_a`
+    pub oracle_a: Option<String>,
+    pub oracle_b: Option<String>,
+}
+
+impl ObV2MarketInfo {
+    pub fn decode(market: &str, program_id: &str, data: &[u8]) -> anyhow::Result<Self> {
+        if data.len() < mem::size_of::<Market>() + 8 {
+            anyhow::bail!("market `{}`: account data too small", market);
+        }
+        if data[..8] != Market::DISCRIMINATOR {
+            anyhow::bail!("market `{}`: not an openbook v2 market account", market);
+        }
+
+        let state = bytemuck::from_bytes::<Market>(&data[8..mem::size_of::<Market>() + 8]);
+
+        Ok(Self {
+            market: market.to_string(),
+            name: state.name().to_string(),
+            program_id: program_id.to_string(),
+            bids: state.bids.to_string(),
+            asks: state.asks.to_string(),
+            event_heap: state.event_heap.to_string(),
+            base_mint: state.base_mint.to_string(),
+            quote_mint: state.quote_mint.to_string(),
+            base_decimals: state.base_decimals,
+            quote_decimals: state.quote_decimals,
+            base_lot_size: state.base_lot_size as u64,
+            quote_lot_size: state.quote_lot_size as u64,
+            maker_fee: state.maker_fee,
+            taker_fee: state.taker_fee,
+            oracle_a: Option::<Pubkey>::from(state.oracle_a).map(|key| key.to_string()),
+            oracle_b: Option::<Pubkey>::from(state.oracle_b).map(|key| key.to_string()),
+        })
+    }
+
+    pub fn units(&self) -> MarketUnits {
+        MarketUnits {
+            base_lot_size: self.base_lot_size,
+            quote_lot_size: self.quote_lot_size,
+            base_decimals: self.base_decimals,
+            quote_decimals: self.quote_decimals,
+        }
+    }
+
+    /// Configured oracle accounts, `oracle_a` first
+    pub fn oracles(&self) -> Vec<String> {
+        self.oracle_a
+            .iter()
+            .chain(self.oracle_b.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// Fetch market account through rpc and decode it
+    pub async fn load(client: &RpcClient, market: &str) -> anyhow::Result<Self> {
+        let market_pubkey = Pubkey::from_str(market)
+            .map_err(|e| anyhow::anyhow!("market `{}`: invalid pubkey: {}", market, e))?;
+        let account = client
+            .get_account(&market_pubkey)
+            .await
+            .map_err(|e| anyhow::anyhow!("market `{}`: failed to fetch account: {}", market, e))?;
+
+        Self::decode(market, &account.owner.to_string(), &account.data)
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::BTreeMap;
+
+use decimal::Decimal;
+
+use crate::structs::{BotMsg, IndicatorMsg, ObV2Level, ObV2MarketView};
+use crate::units::Price;
+use crate::Aggregator;
+
+/// Last known state of one book side
+#[derive(Clone, Debug, Default)]
+pub struct ObV2SideState {
+    pub best: Option<Price>,
+    pub best_lots: Option<i64>,
+    pub levels: BTreeMap<i64, ObV2Level>,
+    pub slot: u64,
+    /// `seq` of the last applied snapshot or diff, and of the snapshot diffs build on
+    pub seq: u64,
+    pub snapshot_seq: u64,
+    pub is_loaded: bool,
+}
+
+impl ObV2SideState {
+    /// Apply a snapshot or diff of the books plugin. A snapshot always replaces the side,
+    /// diffs of stale slots are ignored and a missed diff waits for the next snapshot.
+    fn apply(&mut self, name: &str, slot: u64, data: &BotMsg) -> bool {
+        match data {
+            BotMsg::ObV2Books(books) => {
+                self.seq = books.seq;
+                self.snapshot_seq = books.seq;
+                self.best = books.best;
+                self.best_lots = books.best_lots;
+                self.levels = books
+                    .levels
+                    .iter()
+                    .map(|level| (level.price_lots, level.clone()))
+                    .collect();
+            }
+            BotMsg::ObV2BookDiff(diff) => {
+                // Diffs can't be applied before the first snapshot
+                if !self.is_loaded || slot < self.slot {
+                    return false;
+                }
+                if diff.snapshot_seq != self.snapshot_seq || diff.seq != self.seq + 1 {
+                    tracing::warn!(
+                        "{}: missed diff, got {} on snapshot {}, expected {} on snapshot {}",
+                        name,
+                        diff.seq,
+                        diff.snapshot_seq,
+                        self.seq + 1,
+                        self.snapshot_seq
+                    );
+                    self.is_loaded = false;
+                    return false;
+                }
+
+                self.seq = diff.seq;
+                self.best = diff.best;
+                self.best_lots = diff.best_lots;
+                for level in diff.levels.iter() {
+                    if level.amount.is_zero() {
+                        self.levels.remove(&level.price_lots);
+                    } else {
+                        self.levels.insert(level.price_lots, level.clone());
+                    }
+                }
+            }
+            _ => return false,
+        }
+
+        self.slot = slot;
+        self.is_loaded = true;
+        true
+    }
+}
+
+/// Joins bids and asks books plugins of one market into a two-sided view
+#[derive(Clone, Debug, Default)]
+pub struct ObV2MarketViewPlugin {
+    pub indicator_name: String,
+    pub bids_name: String,
+    pub asks_name: String,
+    /// Number of levels per side in the output
+    pub depth: usize,
+    pub bids: ObV2SideState,
+    pub asks: ObV2SideState,
+}
+
+impl Aggregator for ObV2MarketViewPlugin {
+    fn name(&self) -> String {
+        self.indicator_name.clone()
+    }
+
+    fn sources(&self) -> Vec<String> {
+        vec![self.bids_name.clone(), self.asks_name.clone()]
+    }
+
+    fn aggregate(&mut self, msg: &IndicatorMsg) -> anyhow::Result<BotMsg> {
+        let is_updated = if msg.name.eq(&self.bids_name) {
+            self.bids.apply(&msg.name, msg.slot, &msg.data)
+        } else if msg.name.eq(&self.asks_name) {
+            self.asks.apply(&msg.name, msg.slot, &msg.data)
+        } else {
+            false
+        };
+
+        // Wait for both sides
+        if !is_updated || !self.bids.is_loaded || !self.asks.is_loaded {
+            return Ok(BotMsg::Unimplemented);
+        }
+
+        let best_bid = self.bids.best;
+        let best_ask = self.asks.best;
+        let (mid, spread_bps) = match (best_bid, best_ask) {
+            (Some(bid), Some(ask)) => {
+                // Halves first, the sum could overflow
+                let mid = Price::new(bid.v / 2 + ask.v / 2 + (bid.v % 2 + ask.v % 2) / 2);
+                // Exact difference, only the ratio is rounded
+                let spread = if ask >= bid {
+                    (ask.v - bid.v) as f64
+                } else {
+                    -((bid.v - ask.v) as f64)
+                };
+                (Some(mid), Some(spread / mid.v as f64 * 10_000.0))
+            }
+            _ => (None, None),
+        };
+        let is_crossed = match (best_bid, best_ask) {
+            (Some(bid), Some(ask)) => bid >= ask,
+            _ => false,
+        };
+
+        // Best first on both sides
+        let bids = self
+            .bids
+            .levels
+            .values()
+            .rev()
+            .take(self.depth)
+            .cloned()
+            .collect::<Vec<_>>();
+        let asks = self
+            .asks
+            .levels
+            .values()
+            .take(self.depth)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if is_crossed {
+            tracing::warn!(
+                "{}: crossed book, bid {:?} ask {:?}",
+                self.indicator_name,
+                best_bid,
+                best_ask
+            );
+        }
+
+        Ok(BotMsg::ObV2Market(ObV2MarketView {
+            best_bid,
+            best_bid_lots: self.bids.best_lots,
+            best_ask,
+            best_ask_lots: self.asks.best_lots,
+            mid,
+            spread_bps,
+            is_crossed,
+            bids,
+            asks,
+            bids_slot: self.bids.slot,
+            asks_slot: self.asks.slot,
+            slot_skew: self.bids.slot.abs_diff(self.asks.slot),
+        }))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::structs::{ObV2BookDiff, ObV2BooksData};
+    use crate::units::MarketUnits;
+
+    const UNITS: MarketUnits = MarketUnits {
+        base_lot_size: 1_000_000,
+        quote_lot_size: 1,
+        base_decimals: 9,
+        quote_decimals: 6,
+    };
+
+    fn plugin() -> ObV2MarketViewPlugin {
+        ObV2MarketViewPlugin {
+            indicator_name: "ob_v2_sol_usdc_market".to_string(),
+            bids_name: "ob_v2_sol_usdc_bids".to_string(),
+            asks_name: "ob_v2_sol_usdc_asks".to_string(),
+            depth: 2,
+            ..Default::default()
+        }
+    }
+
+    fn levels(levels: &[(i64, i64)]) -> Vec<ObV2Level> {
+        levels
+            .iter()
+            .map(|(price_lots, quantity)| ObV2Level {
+                price_lots: *price_lots,
+                price: UNITS.price(*price_lots),
+                amount: UNITS.base_lots(*quantity),
+                amount_lots: *quantity,
+                amount_native: UNITS.base_lots_native(*quantity),
+            })
+            .collect()
+    }
+
+    fn snapshot(name: &str, slot: u64, seq: u64, best: i64, side: &[(i64, i64)]) -> IndicatorMsg {
+        IndicatorMsg::new(
+            name.to_string(),
+            slot,
+            0,
+            BotMsg::ObV2Books(ObV2BooksData {
+                seq,
+                best: Some(UNITS.price(best)),
+                best_lots: Some(best),
+                books: vec![],
+                levels: levels(side),
+            }),
+        )
+    }
+
+    fn diff(
+        name: &str,
+        slot: u64,
+        (seq, snapshot_seq): (u64, u64),
+        best: i64,
+        changed: &[(i64, i64)],
+    ) -> IndicatorMsg {
+        IndicatorMsg::new(
+            name.to_string(),
+            slot,
+            0,
+            BotMsg::ObV2BookDiff(ObV2BookDiff {
+                seq,
+                snapshot_seq,
+                best: Some(UNITS.price(best)),
+                best_lots: Some(best),
+                added: vec![],
+                removed: vec![],
+                changed: vec![],
+                levels: levels(changed),
+            }),
+        )
+    }
+
+    fn market_view(data: BotMsg) -> ObV2MarketView {
+        match data {
+            BotMsg::ObV2Market(view) => view,
+            other => panic!("expected a market view, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_join_sides() {
+        let mut plugin = plugin();
+        let bids = plugin.bids_name.clone();
+        let asks = plugin.asks_name.clone();
+
+        // Waits for both sides
+        let msg = snapshot(&bids, 100, 1, 100, &[(98, 1), (99, 2), (100, 3)]);
+        assert_eq!(plugin.aggregate(&msg).unwrap(), BotMsg::Unimplemented);
+
+        let msg = snapshot(&asks, 104, 1, 102, &[(102, 4), (103, 5), (104, 6)]);
+        let view = market_view(plugin.aggregate(&msg).unwrap());
+        assert_eq!(view.best_bid_lots, Some(100));
+        assert_eq!(view.best_ask_lots, Some(102));
+        assert_eq!(view.mid, Some(UNITS.price(101)));
+        assert!((view.spread_bps.unwrap() - 20_000.0 / 101.0).abs() < 1e-9);
+        assert!(!view.is_crossed);
+        // Best first, cut at depth
+        assert_eq!(view.bids, levels(&[(100, 3), (99, 2)]));
+        assert_eq!(view.asks, levels(&[(102, 4), (103, 5)]));
+        assert_eq!(
+            (view.bids_slot, view.asks_slot, view.slot_skew),
+            (100, 104, 4)
+        );
+
+        // Zero amount removes the level
+        let msg = diff(&bids, 105, (2, 1), 99, &[(100, 0)]);
+        let view = market_view(plugin.aggregate(&msg).unwrap());
+        assert_eq!(view.bids, levels(&[(99, 2), (98, 1)]));
+        assert_eq!(view.slot_skew, 1);
+    }
+
+    #[test]
+    fn test_snapshot_replaces_side() {
+        let mut plugin = plugin();
+        let bids = plugin.bids_name.clone();
+        let asks = plugin.asks_name.clone();
+
+        plugin
+            .aggregate(&snapshot(&asks, 100, 1, 102, &[(102, 1)]))
+            .unwrap();
+        plugin
+            .aggregate(&snapshot(&bids, 100, 1, 100, &[(100, 1)]))
+            .unwrap();
+        plugin
+            .aggregate(&diff(&bids, 110, (2, 1), 101, &[(101, 1)]))
+            .unwrap();
+
+        // Resync snapshot read at an older slot than the last diff, no level survives it
+        let msg = snapshot(&bids, 105, 3, 99, &[(99, 7)]);
+        let view = market_view(plugin.aggregate(&msg).unwrap());
+        assert_eq!(view.bids, levels(&[(99, 7)]));
+        assert_eq!(view.bids_slot, 105);
+
+        // Diffs older than the side are stale
+        let msg = diff(&bids, 104, (4, 3), 98, &[(98, 1)]);
+        assert_eq!(plugin.aggregate(&msg).unwrap(), BotMsg::Unimplemented);
+    }
+
+    #[test]
+    fn test_missed_diff() {
+        let mut plugin = plugin();
+        let bids = plugin.bids_name.clone();
+        let asks = plugin.asks_name.clone();
+
+        plugin
+            .aggregate(&snapshot(&asks, 100, 1, 102, &[(102, 1)]))
+            .unwrap();
+        plugin
+            .aggregate(&snapshot(&bids, 100, 1, 100, &[(100, 1)]))
+            .unwrap();
+
+        // Diff 2 never arrived, the side waits for a snapshot
+        let msg = diff(&bids, 102, (3, 1), 101, &[(101, 1)]);
+        assert_eq!(plugin.aggregate(&msg).unwrap(), BotMsg::Unimplemented);
+        assert!(!plugin.bids.is_loaded);
+        let msg = diff(&bids, 103, (4, 1), 101, &[(100, 0)]);
+        assert_eq!(plugin.aggregate(&msg).unwrap(), BotMsg::Unimplemented);
+
+        let msg = snapshot(&bids, 104, 5, 101, &[(101, 1)]);
+        let view = market_view(plugin.aggregate(&msg).unwrap());
+        assert_eq!(view.bids, levels(&[(101, 1)]));
+
+        let msg = diff(&bids, 105, (6, 5), 101, &[(101, 2)]);
+        let view = market_view(plugin.aggregate(&msg).unwrap());
+        assert_eq!(view.bids, levels(&[(101, 2)]));
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::HashSet;
+
+use crate::obv2::ObV2FillDedupe;
+use crate::structs::{BotMsg, IndicatorMsg, ObV2Event, ObV2Fill, ObV2MyFill, ObV2Role};
+use crate::Aggregator;
+
+/// Private trade feed: fills of the events and transactions plugins of one market
+/// where a watched account is the maker or the taker
+#[derive(Clone, Debug, Default)]
+pub struct ObV2MyFillsPlugin {
+    pub indicator_name: String,
+    pub market: String,
+    /// Events and/or transactions indicator names of the market
+    pub sources: Vec<String>,
+    /// Open orders accounts (or owners placing take-only orders) to follow
+    pub watchlist: HashSet<String>,
+    /// A fill seen by both sources is reported once
+    pub dedupe: ObV2FillDedupe,
+}
+
+impl ObV2MyFillsPlugin {
+    fn my_fills(&self, fill: &ObV2Fill) -> Vec<ObV2MyFill> {
+        let mut fills = vec![];
+
+        // Both sides are watched on self-trades
+        if self.watchlist.contains(&fill.maker) {
+            fills.push(ObV2MyFill {
+                account: fill.maker.clone(),
+                counterparty: fill.taker.clone(),
+                role: ObV2Role::Maker,
+                is_buy: !fill.is_buy,
+                price: fill.price,
+                price_lots: fill.price_lots,
+                amount: fill.amount,
+                amount_lots: fill.amount_lots,
+                amount_native: fill.amount_native,
+                fee: fill.maker_fee,
+                fee_native: fill.maker_fee_native,
+                client_order_id: fill.order_id,
+                seq_num: fill.seq_num,
+            });
+        }
+        if self.watchlist.contains(&fill.taker) {
+            fills.push(ObV2MyFill {
+                account: fill.taker.clone(),
+                counterparty: fill.maker.clone(),
+                role: ObV2Role::Taker,
+                is_buy: fill.is_buy,
+                price: fill.price,
+                price_lots: fill.price_lots,
+                amount: fill.amount,
+                amount_lots: fill.amount_lots,
+                amount_native: fill.amount_native,
+                fee: fill.taker_fee,
+                fee_native: fill.taker_fee_native,
+                client_order_id: fill.taker_client_order_id,
+                seq_num: fill.seq_num,
+            });
+        }
+
+        fills
+    }
+}
+
+impl Aggregator for ObV2MyFillsPlugin {
+    fn name(&self) -> String {
+        self.indicator_name.clone()
+    }
+
+    fn sources(&self) -> Vec<String> {
+        self.sources.clone()
+    }
+
+    fn aggregate(&mut self, msg: &IndicatorMsg) -> anyhow::Result<BotMsg> {
+        let fills = self
+            .dedupe
+            .new_fills(&self.market, &msg.data)
+            .into_iter()
+            .filter_map(|envelope| match &envelope.event {
+                ObV2Event::Fill(fill) => Some(
+                    self.my_fills(fill)
+                        .into_iter()
+                        .map(|my_fill| envelope.with_event(my_fill)),
+                ),
+                _ => None,
+            })
+            .flatten()
+            .collect::<Vec<_>>();
+
+        if fills.is_empty() {
+            return Ok(BotMsg::Unimplemented);
+        }
+
+        tracing::info!("{}: {} fills", self.indicator_name, fills.len());
+        Ok(BotMsg::ObV2MyFills(fills))
+    }
+}
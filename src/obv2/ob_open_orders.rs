@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::mem;
+use std::str::FromStr;
+
+use crate::commitment::Commitment;
+use crate::error::GeyserError;
+use crate::structs::{
+    Account, BotMsg, ObV2OpenOrder, ObV2OpenOrdersData, ObV2OpenOrdersDiff, ObV2Position,
+};
+use crate::units::{MarketUnits, RawPosition};
+use crate::utils::{is_buy, load_account_data};
+use crate::Extractor;
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::Discriminator;
+use async_trait::async_trait;
+use openbook_v2::state::{BookSideOrderTree, OpenOrdersAccount};
+use solana_client::nonblocking::rpc_client::RpcClient;
+
+/// Tracks one of our `OpenOrdersAccount`s: resting orders, balances and rebates
+#[derive(Clone, Debug, Default)]
+pub struct ObV2OpenOrdersPlugin {
+    pub indicator_name: String,
+    pub account: String,
+    pub program_id: String,
+    pub units: MarketUnits,
+    pub commitment: Commitment,
+    /// Orders of the previous update, keyed by order id
+    pub orders: HashMap<u128, ObV2OpenOrder>,
+    pub position: Option<ObV2Position>,
+}
+
+impl ObV2OpenOrdersPlugin {
+    /// Market of an open orders account, used to attach `OOS_KEY` accounts to a configured market
+    pub async fn market_of(client: &RpcClient, account: &str) -> anyhow::Result<String> {
+        let account_pubkey = Pubkey::from_str(account)
+            .map_err(|e| anyhow::anyhow!("open orders `{}`: invalid pubkey: {}", account, e))?;
+        let data = client
+            .get_account_data(&account_pubkey)
+            .await
+            .map_err(|e| {
+                anyhow::anyhow!("open orders `{}`: failed to fetch account: {}", account, e)
+            })?;
+
+        let size = mem::size_of::<OpenOrdersAccount>() + 8;
+        if data.len() < size || data[..8] != OpenOrdersAccount::DISCRIMINATOR {
+            anyhow::bail!(
+                "open orders `{}`: not an openbook v2 open orders account",
+                account
+            );
+        }
+
+        let open_orders = bytemuck::from_bytes::<OpenOrdersAccount>(&data[8..size]);
+        Ok(open_orders.market.to_string())
+    }
+}
+
+#[async_trait]
+impl Extractor for ObV2OpenOrdersPlugin {
+    fn name(&self) -> String {
+        self.indicator_name.clone()
+    }
+
+    fn program_id(&self) -> String {
+        self.program_id.clone()
+    }
+    fn account(&self) -> String {
+        self.account.clone()
+    }
+
+    fn commitment(&self) -> Commitment {
+        self.commitment
+    }
+
+    async fn load(&mut self, client: &RpcClient) -> anyhow::Result<BotMsg> {
+        // Next update is sent as a full snapshot
+        self.position = None;
+
+        let account_pubkey = Pubkey::from_str(&self.account)
+            .map_err(|_| GeyserError::InvalidPubkey(self.account.clone()))?;
+        let account = client.get_account(&account_pubkey).await?;
+
+        self.extract(&mut Account {
+            is_startup: false,
+            slot: 0,
+            pubkey: account_pubkey,
+            lamports: account.lamports,
+            owner: account.owner,
+            executable: account.executable,
+            rent_epoch: account.rent_epoch,
+            data: account.data,
+            write_version: 0,
+            txn_signature: String::new(),
+            block_time: None,
+        })
+    }
+
+    fn extract(&mut self, account: &mut Account) -> anyhow::Result<BotMsg> {
+        let open_orders = load_account_data::<OpenOrdersAccount>(account)?;
+
+        let mut orders: HashMap<u128, ObV2OpenOrder> = HashMap::new();
+        for order in open_orders.open_orders.iter() {
+            if order.is_free() {
+                continue;
+            }
+
+            let side_and_tree = order.side_and_tree();
+            orders.insert(
+                order.id,
+                ObV2OpenOrder {
+                    order_id: order.id,
+                    client_order_id: order.client_id,
+                    is_buy: is_buy(side_and_tree.side()),
+                    is_oracle_pegged: side_and_tree.order_tree() == BookSideOrderTree::OraclePegged,
+                    locked_price: self.units.price(order.locked_price),
+                    locked_price_lots: order.locked_price,
+                },
+            );
+        }
+
+        let position = &open_orders.position;
+        let position = self.units.position(&RawPosition {
+            bids_base_lots: position.bids_base_lots,
+            bids_quote_lots: position.bids_quote_lots,
+            asks_base_lots: position.asks_base_lots,
+            base_free_native: position.base_free_native,
+            quote_free_native: position.quote_free_native,
+            locked_maker_fees: position.locked_maker_fees,
+            referrer_rebates_available: position.referrer_rebates_available,
+        });
+
+        // First update after load is a full snapshot
+        let prev_position = match self.position.replace(position.clone()) {
+            Some(prev_position) => prev_position,
+            None => {
+                self.orders = orders;
+                let mut orders = self.orders.values().cloned().collect::<Vec<_>>();
+                orders.sort_by_key(|order| order.order_id);
+
+                return Ok(BotMsg::ObV2OpenOrders(ObV2OpenOrdersData {
+                    owner: open_orders.owner.to_string(),
+                    market: open_orders.market.to_string(),
+                    orders,
+                    position,
+                }));
+            }
+        };
+
+        let added = orders
+            .values()
+            .filter(|order| !self.orders.contains_key(&order.order_id))
+            .cloned()
+            .collect::<Vec<_>>();
+        let removed = self
+            .orders
+            .values()
+            .filter(|order| !orders.contains_key(&order.order_id))
+            .cloned()
+            .collect::<Vec<_>>();
+        self.orders = orders;
+
+        let position = if prev_position != position {
+            Some(position)
+        } else {
+            None
+        };
+
+        if added.is_empty() && removed.is_empty() && position.is_none() {
+            return Ok(BotMsg::Unimplemented);
+        }
+
+        Ok(BotMsg::ObV2OpenOrdersDiff(ObV2OpenOrdersDiff {
+            added,
+            removed,
+            position,
+        }))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::units::{Price, Quantity};
+    use bytemuck::{bytes_of, Zeroable};
+    use openbook_v2::state::SideAndOrderTree;
+
+    /// Base with fewer decimals than quote, e.g. 2 against 6
+    fn plugin() -> ObV2OpenOrdersPlugin {
+        ObV2OpenOrdersPlugin {
+            indicator_name: "oo".to_string(),
+            units: MarketUnits {
+                base_lot_size: 100,
+                quote_lot_size: 10,
+                base_decimals: 2,
+                quote_decimals: 6,
+            },
+            ..Default::default()
+        }
+    }
+
+    fn account(open_orders: &OpenOrdersAccount) -> Account {
+        let mut data = OpenOrdersAccount::DISCRIMINATOR.to_vec();
+        data.extend_from_slice(bytes_of(open_orders));
+        Account {
+            is_startup: false,
+            slot: 10,
+            pubkey: Pubkey::new_unique(),
+            lamports: 0,
+            owner: Pubkey::default(),
+            executable: false,
+            rent_epoch: 0,
+            data,
+            write_version: 1,
+            txn_signature: String::new(),
+            block_time: None,
+        }
+    }
+
+    fn fixture() -> OpenOrdersAccount {
+        let mut open_orders = OpenOrdersAccount::zeroed();
+        open_orders.owner = Pubkey::new_unique();
+        open_orders.market = Pubkey::new_unique();
+        for order in open_orders.open_orders.iter_mut() {
+            order.is_free = true.into();
+        }
+        let order = &mut open_orders.open_orders[3];
+        order.is_free = false.into();
+        order.id = 42;
+        order.client_id = 7;
+        order.locked_price = 250_000;
+        order.side_and_tree = SideAndOrderTree::BidFixed.into();
+
+        open_orders.position.bids_base_lots = 3;
+        open_orders.position.bids_quote_lots = 750_000;
+        open_orders.position.base_free_native = 250;
+        open_orders.position.quote_free_native = 1_500_000;
+        open_orders
+    }
+
+    fn price(s: &str) -> Price {
+        s.parse().unwrap()
+    }
+
+    fn quantity(s: &str) -> Quantity {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_extract_fixture() {
+        let mut plugin = plugin();
+        let open_orders = fixture();
+
+        let BotMsg::ObV2OpenOrders(data) = plugin.extract(&mut account(&open_orders)).unwrap()
+        else {
+            panic!("expected a snapshot");
+        };
+        assert_eq!(data.owner, open_orders.owner.to_string());
+        assert_eq!(data.market, open_orders.market.to_string());
+        assert_eq!(
+            data.orders,
+            vec![ObV2OpenOrder {
+                order_id: 42,
+                client_order_id: 7,
+                is_buy: true,
+                is_oracle_pegged: false,
+                // 250_000 quote lots of 10 per base lot of 100, 2.5 quote per base
+                locked_price: price("2.5"),
+                locked_price_lots: 250_000,
+            }]
+        );
+        assert_eq!(data.position.bids_base, quantity("3"));
+        assert_eq!(data.position.quote_locked, quantity("7.5"));
+        assert_eq!(data.position.quote_locked_native, 7_500_000);
+        assert_eq!(data.position.base_free, quantity("2.5"));
+        assert_eq!(data.position.quote_free, quantity("1.5"));
+
+        // Unchanged account, nothing to send
+        assert_eq!(
+            plugin.extract(&mut account(&open_orders)).unwrap(),
+            BotMsg::Unimplemented
+        );
+
+        // Order filled, only the diff goes out
+        let mut filled = open_orders;
+        filled.open_orders[3].is_free = true.into();
+        filled.position.bids_base_lots = 0;
+        filled.position.bids_quote_lots = 0;
+        filled.position.base_free_native = 550;
+        let BotMsg::ObV2OpenOrdersDiff(diff) = plugin.extract(&mut account(&filled)).unwrap()
+        else {
+            panic!("expected a diff");
+        };
+        assert!(diff.added.is_empty());
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.position.unwrap().base_free, quantity("5.5"));
+    }
+}
@@ -1,25 +1,44 @@
 use anchor_lang::AnchorDeserialize;
 use anchor_lang::Discriminator;
-use base64::{prelude::BASE64_STANDARD, Engine};
-use bytemuck::{self, cast_ref};
-use openbook_v2::logs::{FillLog, OpenOrdersPositionLog, SettleFundsLog};
-use openbook_v2::state::FillEvent;
+use openbook_v2::logs::{
+    DepositLog, FillLog, MarketMetaDataLog, OpenOrdersPositionLog, SetDelegateLog, SettleFundsLog,
+    SweepFeesLog, TotalOrderFillEvent,
+};
 
-use crate::structs::{Account, BotMsg, MessageTransaction, ObV2Cancel, ObV2Event, ObV2Fill};
+use crate::backfill::{
+    replay, Backfill, SignatureWindow, BACKFILL_CONCURRENCY, BACKFILL_MAX_SIGNATURES,
+};
+use crate::commitment::Commitment;
+use crate::error::GeyserError;
+use crate::log_parser::parse_logs;
+use crate::obv2::{decode_instruction, resolve_accounts};
+use crate::structs::{
+    BotMsg, MessageTransaction, ObV2Deposit, ObV2Envelope, ObV2Event, ObV2Fill, ObV2Instruction,
+    ObV2MarketMetaData, ObV2PositionLog, ObV2SetDelegate, ObV2SettleFunds, ObV2SweepFees,
+    ObV2TotalOrderFill, ObV2TransactionData, ObV2UnknownLog, ParsedTransaction,
+};
+use crate::units::{Fee, MarketUnits, RawPosition};
+use crate::utils::event_ix_data;
 use crate::utils::is_buy;
-use crate::utils::token_decimals;
 use crate::Parser;
+use anchor_lang::prelude::Pubkey;
 use async_trait::async_trait;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::signature::Signature;
+use solana_transaction_status::UiTransactionEncoding;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct ObV2TransactionsPlugin {
     pub indicator_name: String,
     pub account: String,
     pub program_id: String,
-    pub base_lot_size: u64,
-    pub quote_lot_size: u64,
-    pub base_decimals: u8,
-    pub quote_decimals: u8,
+    pub units: MarketUnits,
+    pub commitment: Commitment,
 }
 
 #[async_trait]
@@ -35,116 +54,575 @@ impl Parser for ObV2TransactionsPlugin {
         self.account.clone()
     }
 
+    fn commitment(&self) -> Commitment {
+        self.commitment
+    }
+
     fn parse(&self, tx: &MessageTransaction) -> anyhow::Result<BotMsg> {
-        let slot = tx.slot;
+        self.parse_transaction(&tx.to_parsed_transaction()?)
+    }
+
+    async fn backfill(
+        &self,
+        client: &RpcClient,
+        from_slot: u64,
+        to_slot: u64,
+    ) -> anyhow::Result<Backfill> {
+        let account = Pubkey::from_str(&self.account)?;
+
+        // Signatures come newest first, page back until the last seen slot
+        let mut window = SignatureWindow::new(from_slot, to_slot, BACKFILL_MAX_SIGNATURES);
+        let mut before: Option<Signature> = None;
+        while !window.is_done {
+            let page = client
+                .get_signatures_for_address_with_config(
+                    &account,
+                    GetConfirmedSignaturesForAddress2Config {
+                        before,
+                        until: None,
+                        limit: None,
+                        commitment: Some(CommitmentConfig::confirmed()),
+                    },
+                )
+                .await;
+            let page = match page {
+                Ok(page) => page,
+                Err(e) => {
+                    // Keep what was paged, older slots go out as a gap
+                    tracing::warn!("{}: backfill paging failed: {}", self.indicator_name, e);
+                    window.cut(window.oldest_slot());
+                    break;
+                }
+            };
+            window.push_page(&page);
+            if let Some(last) = page.last() {
+                before = Some(Signature::from_str(&last.signature)?);
+            }
+        }
+
+        let backfill = replay(&window, BACKFILL_CONCURRENCY, |signature| async move {
+            let tx = client
+                .get_transaction_with_config(
+                    &Signature::from_str(&signature)?,
+                    RpcTransactionConfig {
+                        encoding: Some(UiTransactionEncoding::Base64),
+                        commitment: Some(CommitmentConfig::confirmed()),
+                        max_supported_transaction_version: Some(0),
+                    },
+                )
+                .await?;
+            let transaction = ParsedTransaction::from_rpc(&signature, tx)?;
+            Ok((transaction.slot, self.parse_transaction(&transaction)?))
+        })
+        .await;
+
+        tracing::info!(
+            "{}: backfilled {} txs from slot {} to {}, {} failed",
+            self.indicator_name,
+            backfill.msgs.len(),
+            from_slot,
+            to_slot,
+            backfill.errors.len()
+        );
+        Ok(backfill)
+    }
+}
+
+impl ObV2TransactionsPlugin {
+    pub fn parse_transaction(&self, transaction: &ParsedTransaction) -> anyhow::Result<BotMsg> {
+        let slot = transaction.slot;
 
-        let transaction = tx.to_parsed_transaction();
         let account_idx = transaction
             .accounts
             .iter()
             .position(|t| t.eq(&self.account))
-            .unwrap() as u8;
+            .ok_or(GeyserError::AccountNotInTransaction(self.account.clone()))?
+            as u8;
 
         tracing::info!("tx: {}, slot: {}", transaction.signature, slot);
 
-        // No need to parse instructions (because we need generated events)
-        /*
-        // Loop instructions
+        // Top-level and inner (CPI) instructions of the program touching the market,
+        // and `emit_cpi!` events, which are self-CPIs of the program
+        let mut instructions: Vec<ObV2Instruction> = vec![];
+        let mut market_ixs: HashSet<(usize, Option<usize>)> = HashSet::new();
+        let mut cpi_events: Vec<(usize, Option<usize>, (usize, Option<usize>), &[u8])> = vec![];
         for (index, ix) in transaction.instructions.iter().enumerate() {
-            if ix.program_id.eq(&self.program_id) && ix.accounts.contains(&account_idx) {
-                // Check instruction type
-                let discriminator = &ix.data[0..8];
-                if discriminator == CancelOrder::DISCRIMINATOR {
-                    tracing::info!("cancel order");
-
-                    let tx = CancelOrder::deserialize(&mut &ix.data[8..])?;
-                } else if discriminator == CancelAllAndPlaceOrders::DISCRIMINATOR {
-                    tracing::info!("cancel & place orders");
-
-                    let tx = CancelAllAndPlaceOrders::deserialize(&mut &ix.data[8..])?;
-                } else if discriminator == PlaceOrder::DISCRIMINATOR {
-                    tracing::info!("place order");
-
-                    let tx = PlaceOrder::deserialize(&mut &ix.data[8..])?;
-                } else if discriminator == PlaceTakeOrder::DISCRIMINATOR {
-                    tracing::info!("place take order");
-
-                    let tx = PlaceTakeOrder::deserialize(&mut &ix.data[8..])?;
-                } else if discriminator == PlaceOrders::DISCRIMINATOR {
-                    tracing::info!("place multiple orders");
-
-                    let tx = PlaceOrders::deserialize(&mut &ix.data[8..])?;
-                } else if discriminator == SettleFunds::DISCRIMINATOR {
-                    tracing::info!("settle funds");
-                } else if discriminator == ConsumeEvents::DISCRIMINATOR {
-                    tracing::info!("consume events");
-                } else {
-                    tracing::info!("unknown obv2 ix");
+            let inner_ixs = transaction
+                .inner_instructions
+                .get(index)
+                .map(|inner_ixs| inner_ixs.as_slice())
+                .unwrap_or_default();
+
+            let ixs = std::iter::once((None, ix))
+                .chain(inner_ixs.iter().enumerate().map(|(i, ix)| (Some(i), ix)));
+            // Program instruction an `emit_cpi!` event comes from, the last one before it
+            let mut emitter = (index, None);
+            for (inner_index, ix) in ixs {
+                if !ix.program_id.eq(&self.program_id) {
+                    continue;
+                }
+                if let Some(data) = inner_index.and(event_ix_data(&ix.data)) {
+                    cpi_events.push((index, inner_index, emitter, data));
+                    continue;
                 }
+                emitter = (index, inner_index);
+                if !ix.accounts.contains(&account_idx) {
+                    continue;
+                }
+                market_ixs.insert((index, inner_index));
+
+                let instruction = match decode_instruction(&ix.data, &self.units) {
+                    Ok(instruction) => instruction,
+                    Err(e) => {
+                        tracing::warn!("tx {}: undecodable obv2 ix: {}", transaction.signature, e);
+                        continue;
+                    }
+                };
+                let accounts = match resolve_accounts(&instruction, ix, &transaction.accounts) {
+                    Ok(accounts) => accounts,
+                    Err(e) => {
+                        tracing::warn!(
+                            "tx {}: unresolved obv2 ix accounts: {}",
+                            transaction.signature,
+                            e
+                        );
+                        continue;
+                    }
+                };
+                instructions.push(ObV2Instruction {
+                    index,
+                    inner_index,
+                    instruction,
+                    accounts,
+                });
             }
         }
-        */
-
-        let mut events: Vec<ObV2Event> = vec![];
-        let price_factor = token_decimals(self.base_decimals - self.quote_decimals)
-            * self.quote_lot_size as f64
-            / self.base_lot_size as f64;
-        let base_factor = self.base_lot_size as f64 / token_decimals(self.base_decimals);
-
-        // Check logs
-        let mut start_idx: i16 = -1;
-        for (idx, log) in transaction.logs.iter().enumerate() {
-            // check openbookv2 events only
-            if log.eq(&format!("Program {} invoke [1]", self.program_id)) {
-                start_idx = idx as i16;
-                continue;
+
+        // Inner instruction data can't be truncated, unlike logs
+        let mut events: Vec<(usize, Option<usize>, ObV2Event)> = vec![];
+        let mut seen: HashMap<&[u8], usize> = HashMap::new();
+        for (index, inner_index, emitter, data) in cpi_events.iter().copied() {
+            *seen.entry(data).or_default() += 1;
+            match self.decode_event(&transaction.signature, data) {
+                Ok(event) if self.is_market_event(&market_ixs, emitter, &event) => {
+                    events.push((index, inner_index, event))
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!("tx {}: invalid obv2 event: {}", transaction.signature, e),
             }
+        }
 
-            // if openbookv2's ix finished
-            if log.eq(&format!("Program {} success", self.program_id)) {
-                start_idx = -1;
+        // Events of the program in logs, at any invoke depth (e.g. routed through an
+        // aggregator), unless already seen as inner instructions
+        let logs = parse_logs(&transaction.logs);
+        for entry in logs.data.iter() {
+            // Invocations cut off by truncated logs returned, since the transaction succeeded
+            let is_success = entry.is_success || (logs.is_truncated && !transaction.is_err);
+            if !entry.program_id.eq(&self.program_id) || !is_success {
                 continue;
             }
+            if let Some(count) = seen.get_mut(entry.data.as_slice()) {
+                if *count > 0 {
+                    *count -= 1;
+                    continue;
+                }
+            }
 
-            // if in openbookv2 instruction
-            if start_idx > -1 && log.starts_with("Program data:") {
-                match BASE64_STANDARD.decode(&log[14..]) {
-                    Ok(data) => {
-                        // Check event type
-                        let discriminator = &data[0..8];
-                        if discriminator == FillLog::DISCRIMINATOR {
-                            tracing::info!("fill order");
-
-                            let fill = FillLog::deserialize(&mut &data[8..])?;
-
-                            events.push(ObV2Event::Fill(ObV2Fill {
-                                is_buy: fill.taker_side == 0,
-                                taker: fill.taker.to_string(),
-                                maker: fill.maker.to_string(),
-                                order_id: fill.maker_client_order_id,
-                                price: (fill.price as f64) * price_factor,
-                                amount: fill.quantity as f64 * base_factor,
-                            }));
-                        } else if discriminator == OpenOrdersPositionLog::DISCRIMINATOR {
-                            tracing::info!("positions order");
-                        } else if discriminator == SettleFundsLog::DISCRIMINATOR {
-                            // tracing::info!("settle funds");
-                        } else {
-                            tracing::info!("other event: {:?}", discriminator);
-                        }
-                    }
-                    Err(_) => {
-                        tracing::error!("Parse obv2 data error");
-                    }
+            let emitter = (entry.index, entry.inner_index);
+            match self.decode_event(&transaction.signature, &entry.data) {
+                Ok(event) if self.is_market_event(&market_ixs, emitter, &event) => {
+                    events.push((entry.index, entry.inner_index, event))
                 }
+                Ok(_) => {}
+                Err(e) => tracing::warn!("tx {}: invalid obv2 event: {}", transaction.signature, e),
             }
         }
 
-        if events.len() > 0 {
-            tracing::info!("total events: {:?}", events.len());
-            Ok(BotMsg::ObV2Events(events))
-        } else {
-            Ok(BotMsg::Unimplemented)
+        if logs.is_truncated {
+            tracing::warn!(
+                "tx {}: logs truncated, {} events from inner instructions",
+                transaction.signature,
+                cpi_events.len()
+            );
+        }
+
+        if instructions.is_empty() && events.is_empty() {
+            return Ok(BotMsg::Unimplemented);
         }
+
+        tracing::info!(
+            "total instructions: {}, events: {}",
+            instructions.len(),
+            events.len()
+        );
+
+        // Emission order, stable within an instruction
+        events.sort_by_key(|(index, inner_index, _)| (*index, *inner_index));
+        let signature = Some(transaction.signature.clone());
+        let events = events
+            .into_iter()
+            .enumerate()
+            .map(|(seq, (index, inner_index, event))| ObV2Envelope {
+                slot: transaction.slot,
+                block_time: transaction.block_time,
+                signature: signature.clone(),
+                tx_index: transaction.index,
+                ix_index: Some(index),
+                inner_ix_index: inner_index,
+                seq: seq as u32,
+                event,
+            })
+            .collect();
+
+        Ok(BotMsg::ObV2Transaction(ObV2TransactionData {
+            signature: transaction.signature.clone(),
+            instructions,
+            events,
+            is_log_truncated: logs.is_truncated,
+        }))
+    }
+
+    /// Events of other markets can share the transaction (e.g. a route through two markets).
+    /// An event is ours if its instruction has the market account, or if it names the market.
+    fn is_market_event(
+        &self,
+        market_ixs: &HashSet<(usize, Option<usize>)>,
+        emitter: (usize, Option<usize>),
+        event: &ObV2Event,
+    ) -> bool {
+        market_ixs.contains(&emitter) || event.market() == Some(self.account.as_str())
+    }
+
+    /// Decode event data (discriminator and borsh payload) of a log or an `emit_cpi!` instruction
+    fn decode_event(&self, signature: &str, data: &[u8]) -> anyhow::Result<ObV2Event> {
+        if data.len() < 8 {
+            return Err(GeyserError::InvalidProgramData(format!("{:?}", data)).into());
+        }
+
+        Ok(self.decode_log(data).unwrap_or_else(|e| {
+            tracing::warn!("tx {}: undecodable obv2 event: {}", signature, e);
+            ObV2Event::Unknown(ObV2UnknownLog {
+                discriminator: data[0..8].to_vec(),
+                data: data[8..].to_vec(),
+            })
+        }))
+    }
+
+    /// Decode event data of the program.
+    /// Logs unknown to the feed are kept raw in `ObV2Event::Unknown`.
+    fn decode_log(&self, data: &[u8]) -> anyhow::Result<ObV2Event> {
+        let units = &self.units;
+        let discriminator = &data[0..8];
+        let args = &mut &data[8..];
+        let event = if discriminator == FillLog::DISCRIMINATOR {
+            let fill = FillLog::deserialize(args)?;
+            ObV2Event::Fill(ObV2Fill {
+                is_buy: fill.taker_side == 0,
+                taker: fill.taker.to_string(),
+                maker: fill.maker.to_string(),
+                order_id: fill.maker_client_order_id,
+                taker_client_order_id: fill.taker_client_order_id,
+                maker_fee: Fee::from_native(fill.maker_fee as i128, units.quote_decimals),
+                maker_fee_native: fill.maker_fee as i64,
+                taker_fee: Fee::from_native(fill.taker_fee_ceil as i128, units.quote_decimals),
+                taker_fee_native: fill.taker_fee_ceil as i64,
+                seq_num: fill.seq_num,
+                price: units.price(fill.price),
+                price_lots: fill.price,
+                amount: units.base_lots(fill.quantity),
+                amount_lots: fill.quantity,
+                amount_native: units.base_lots_native(fill.quantity),
+            })
+        } else if discriminator == OpenOrdersPositionLog::DISCRIMINATOR {
+            let log = OpenOrdersPositionLog::deserialize(args)?;
+            ObV2Event::Position(ObV2PositionLog {
+                owner: log.owner.to_string(),
+                market: log.market.to_string(),
+                open_orders_account_num: log.open_orders_account_num,
+                position: units.position(&RawPosition {
+                    bids_base_lots: log.bids_base_lots,
+                    bids_quote_lots: log.bids_quote_lots,
+                    asks_base_lots: log.asks_base_lots,
+                    base_free_native: log.base_free_native,
+                    quote_free_native: log.quote_free_native,
+                    locked_maker_fees: log.locked_maker_fees,
+                    referrer_rebates_available: log.referrer_rebates_available,
+                }),
+                maker_volume: units.quote(log.maker_volume as u128),
+                maker_volume_native: log.maker_volume as u128,
+                taker_volume: units.quote(log.taker_volume as u128),
+                taker_volume_native: log.taker_volume as u128,
+            })
+        } else if discriminator == SettleFundsLog::DISCRIMINATOR {
+            let log = SettleFundsLog::deserialize(args)?;
+            ObV2Event::SettleFunds(ObV2SettleFunds {
+                open_orders_account: log.open_orders_account.to_string(),
+                base_amount: units.base(log.base_native as u128),
+                base_amount_native: log.base_native,
+                quote_amount: units.quote(log.quote_native as u128),
+                quote_amount_native: log.quote_native,
+                referrer_rebate: units.quote(log.referrer_rebate as u128),
+                referrer_rebate_native: log.referrer_rebate,
+                referrer: log.referrer.map(|referrer| referrer.to_string()),
+            })
+        } else if discriminator == TotalOrderFillEvent::DISCRIMINATOR {
+            let log = TotalOrderFillEvent::deserialize(args)?;
+            // A taker bid pays quote and receives base, an ask the other way around
+            let is_buy = log.side == 0;
+            let (base_native, quote_native) = if is_buy {
+                (log.total_quantity_received, log.total_quantity_paid)
+            } else {
+                (log.total_quantity_paid, log.total_quantity_received)
+            };
+            ObV2Event::TotalOrderFill(ObV2TotalOrderFill {
+                taker: log.taker.to_string(),
+                is_buy,
+                base_amount: units.base(base_native as u128),
+                base_amount_native: base_native,
+                quote_amount: units.quote(quote_native as u128),
+                quote_amount_native: quote_native,
+                fees: units.quote(log.fees as u128),
+                fees_native: log.fees,
+            })
+        } else if discriminator == DepositLog::DISCRIMINATOR {
+            let log = DepositLog::deserialize(args)?;
+            ObV2Event::Deposit(ObV2Deposit {
+                open_orders_account: log.open_orders_account.to_string(),
+                signer: log.signer.to_string(),
+                base_amount: units.base(log.base_amount as u128),
+                base_amount_native: log.base_amount,
+                quote_amount: units.quote(log.quote_amount as u128),
+                quote_amount_native: log.quote_amount,
+            })
+        } else if discriminator == SetDelegateLog::DISCRIMINATOR {
+            let log = SetDelegateLog::deserialize(args)?;
+            ObV2Event::SetDelegate(ObV2SetDelegate {
+                open_orders_account: log.open_orders_account.to_string(),
+                delegate: log.delegate.map(|delegate| delegate.to_string()),
+            })
+        } else if discriminator == MarketMetaDataLog::DISCRIMINATOR {
+            // Decimals of the new market, not of this plugin's market
+            let log = MarketMetaDataLog::deserialize(args)?;
+            ObV2Event::MarketMetaData(ObV2MarketMetaData {
+                market: log.market.to_string(),
+                name: log.name,
+                base_mint: log.base_mint.to_string(),
+                quote_mint: log.quote_mint.to_string(),
+                base_decimals: log.base_decimals,
+                quote_decimals: log.quote_decimals,
+                base_lot_size: log.base_lot_size,
+                quote_lot_size: log.quote_lot_size,
+            })
+        } else if discriminator == SweepFeesLog::DISCRIMINATOR {
+            let log = SweepFeesLog::deserialize(args)?;
+            ObV2Event::SweepFees(ObV2SweepFees {
+                market: log.market.to_string(),
+                amount: units.quote(log.amount as u128),
+                amount_native: log.amount,
+                receiver: log.receiver.to_string(),
+            })
+        } else {
+            ObV2Event::Unknown(ObV2UnknownLog {
+                discriminator: discriminator.to_vec(),
+                data: data[8..].to_vec(),
+            })
+        };
+
+        Ok(event)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::structs::ParsedInstruction;
+    use crate::utils::EVENT_IX_TAG_LE;
+    use anchor_lang::AnchorSerialize;
+    use base64::{prelude::BASE64_STANDARD, Engine};
+
+    #[test]
+    fn test_skip_invalid_items() {
+        let program_id = Pubkey::new_unique().to_string();
+        let market = Pubkey::new_unique().to_string();
+        let plugin = ObV2TransactionsPlugin {
+            indicator_name: "txs".to_string(),
+            account: market.clone(),
+            program_id: program_id.clone(),
+            ..Default::default()
+        };
+
+        let ix = |accounts: Vec<u8>| ParsedInstruction {
+            program_id: program_id.clone(),
+            accounts,
+            data: vec![9; 8],
+        };
+        let event = [vec![1; 8], vec![2, 3]].concat();
+        let transaction = ParsedTransaction {
+            slot: 10,
+            block_time: None,
+            index: Some(0),
+            // Second instruction points past the account keys
+            instructions: vec![ix(vec![0]), ix(vec![0, 7])],
+            inner_instructions: vec![],
+            logs: vec![
+                format!("Program {} invoke [1]", program_id),
+                // Too short for a discriminator
+                format!("Program data: {}", BASE64_STANDARD.encode([1, 2])),
+                format!("Program data: {}", BASE64_STANDARD.encode(&event)),
+                format!("Program {} success", program_id),
+                format!("Program {} invoke [1]", program_id),
+                format!("Program {} success", program_id),
+            ],
+            accounts: vec![market, program_id.clone()],
+            is_err: false,
+            signature: "sig".to_string(),
+        };
+
+        let BotMsg::ObV2Transaction(data) = plugin.parse_transaction(&transaction).unwrap() else {
+            panic!("expected a transaction");
+        };
+        assert_eq!(data.instructions.len(), 1);
+        assert_eq!(data.instructions[0].index, 0);
+        assert_eq!(data.events.len(), 1);
+        assert_eq!(
+            data.events[0].event,
+            ObV2Event::Unknown(ObV2UnknownLog {
+                discriminator: vec![1; 8],
+                data: vec![2, 3],
+            })
+        );
+    }
+
+    #[test]
+    fn test_market_events() {
+        let program_id = Pubkey::new_unique().to_string();
+        let market = Pubkey::new_unique();
+        let other_market = Pubkey::new_unique().to_string();
+        let plugin = ObV2TransactionsPlugin {
+            indicator_name: "txs".to_string(),
+            account: market.to_string(),
+            program_id: program_id.clone(),
+            ..Default::default()
+        };
+
+        let ix = |accounts: Vec<u8>, data: Vec<u8>| ParsedInstruction {
+            program_id: program_id.clone(),
+            accounts,
+            data,
+        };
+        let sweep_fees = [
+            SweepFeesLog::DISCRIMINATOR.to_vec(),
+            SweepFeesLog {
+                market,
+                amount: 5,
+                receiver: Pubkey::new_unique(),
+            }
+            .try_to_vec()
+            .unwrap(),
+        ]
+        .concat();
+        let ours = [vec![1; 8], vec![1]].concat();
+        let theirs = [vec![2; 8], vec![2]].concat();
+        let transaction = ParsedTransaction {
+            slot: 10,
+            block_time: None,
+            index: Some(0),
+            // A route through our market, then another one of the same program
+            instructions: vec![ix(vec![0], vec![9; 8]), ix(vec![1], vec![9; 8])],
+            inner_instructions: vec![
+                vec![],
+                vec![ix(vec![], [EVENT_IX_TAG_LE.to_vec(), vec![3; 9]].concat())],
+            ],
+            logs: vec![
+                format!("Program {} invoke [1]", program_id),
+                format!("Program data: {}", BASE64_STANDARD.encode(&ours)),
+                format!("Program {} success", program_id),
+                format!("Program {} invoke [1]", program_id),
+                format!("Program data: {}", BASE64_STANDARD.encode(&theirs)),
+                // Names our market from an instruction without it
+                format!("Program data: {}", BASE64_STANDARD.encode(&sweep_fees)),
+                format!("Program {} success", program_id),
+            ],
+            accounts: vec![market.to_string(), other_market, program_id.clone()],
+            is_err: false,
+            signature: "sig".to_string(),
+        };
+
+        let BotMsg::ObV2Transaction(data) = plugin.parse_transaction(&transaction).unwrap() else {
+            panic!("expected a transaction");
+        };
+        assert_eq!(data.instructions.len(), 1);
+        let events = data
+            .events
+            .iter()
+            .map(|envelope| (envelope.ix_index, envelope.event.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            events[0],
+            (
+                Some(0),
+                ObV2Event::Unknown(ObV2UnknownLog {
+                    discriminator: vec![1; 8],
+                    data: vec![1],
+                })
+            )
+        );
+        assert_eq!(events[1].0, Some(1));
+        assert_eq!(events[1].1.market(), Some(market.to_string().as_str()));
+    }
+
+    #[test]
+    fn test_truncated_logs() {
+        let program_id = Pubkey::new_unique().to_string();
+        let market = Pubkey::new_unique().to_string();
+        let plugin = ObV2TransactionsPlugin {
+            indicator_name: "txs".to_string(),
+            account: market.clone(),
+            program_id: program_id.clone(),
+            ..Default::default()
+        };
+
+        let event = [vec![1; 8], vec![2, 3]].concat();
+        let transaction = |is_err: bool| ParsedTransaction {
+            slot: 10,
+            block_time: None,
+            index: Some(0),
+            instructions: vec![ParsedInstruction {
+                program_id: program_id.clone(),
+                accounts: vec![0],
+                data: vec![9; 8],
+            }],
+            inner_instructions: vec![],
+            // The invocation never returned in the logs
+            logs: vec![
+                format!("Program {} invoke [1]", program_id),
+                format!("Program data: {}", BASE64_STANDARD.encode(&event)),
+                "Log truncated".to_string(),
+            ],
+            accounts: vec![market.clone(), program_id.clone()],
+            is_err,
+            signature: "sig".to_string(),
+        };
+
+        let BotMsg::ObV2Transaction(data) = plugin.parse_transaction(&transaction(false)).unwrap()
+        else {
+            panic!("expected a transaction");
+        };
+        assert_eq!(data.events.len(), 1);
+        assert_eq!(
+            data.events[0].event,
+            ObV2Event::Unknown(ObV2UnknownLog {
+                discriminator: vec![1; 8],
+                data: vec![2, 3],
+            })
+        );
+
+        // A failed transaction may have failed in the cut off part
+        let BotMsg::ObV2Transaction(data) = plugin.parse_transaction(&transaction(true)).unwrap()
+        else {
+            panic!("expected a transaction");
+        };
+        assert!(data.events.is_empty());
     }
 }
@@ -0,0 +1,190 @@
+use crate::commitment::Commitment;
+use crate::config::{Config, MarketConfig, PluginKind};
+use crate::error::ErrorCounters;
+use crate::obv2::{
+    ObV2BooksPlugin, ObV2EventsPlugin, ObV2FillsPlugin, ObV2MarketInfo, ObV2MarketViewPlugin,
+    ObV2MyFillsPlugin, ObV2OpenOrdersPlugin, ObV2TransactionsPlugin,
+};
+use crate::{Aggregator, Extractor, Parser};
+use anyhow::Context;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use std::collections::HashSet;
+
+#[derive(Default)]
+pub struct PluginRegistry {
+    pub extractors: Vec<Box<dyn Extractor>>,
+    pub parsers: Vec<Box<dyn Parser>>,
+    pub aggregators: Vec<Box<dyn Aggregator>>,
+    pub errors: ErrorCounters,
+}
+
+impl PluginRegistry {
+    /// Commitment of the extractor or parser behind a geyser filter
+    pub fn commitment(&self, name: &str) -> Option<Commitment> {
+        self.extractors
+            .iter()
+            .find(|t| t.name() == name)
+            .map(|t| t.commitment())
+            .or_else(|| {
+                self.parsers
+                    .iter()
+                    .find(|t| t.name() == name)
+                    .map(|t| t.commitment())
+            })
+    }
+
+    /// Commitments of every extractor and parser
+    pub fn commitments(&self) -> Vec<Commitment> {
+        self.extractors
+            .iter()
+            .map(|t| t.commitment())
+            .chain(self.parsers.iter().map(|t| t.commitment()))
+            .collect()
+    }
+
+    /// Build every configured plugin for every market of the config.
+    /// Accounts, decimals and lot sizes are read from the on-chain market account.
+    pub async fn from_config(config: &Config, client: &RpcClient) -> anyhow::Result<Self> {
+        config.validate()?;
+
+        let mut registry = Self::default();
+        for market in config.markets.iter() {
+            let info = ObV2MarketInfo::load(client, &market.market)
+                .await
+                .with_context(|| format!("market `{}`: discovery failed", market.name))?;
+
+            let mut market = market.clone();
+            market.discover(&info)?;
+            tracing::info!(
+                "Discovered market {} ({}): bids {}, asks {}, event heap {}",
+                market.name,
+                info.name,
+                info.bids,
+                info.asks,
+                info.event_heap
+            );
+
+            registry.register_market(&market, &info, &config.watchlist);
+        }
+
+        Ok(registry)
+    }
+
+    fn register_market(
+        &mut self,
+        market: &MarketConfig,
+        info: &ObV2MarketInfo,
+        watchlist: &[String],
+    ) {
+        for plugin in market.plugins.iter() {
+            match plugin {
+                PluginKind::Books => {
+                    // Bids
+                    self.extractors.push(Box::new(ObV2BooksPlugin {
+                        indicator_name: market.indicator_name("bids"),
+                        account: info.bids.clone(),
+                        program_id: market.program_id.clone(),
+                        units: info.units(),
+                        commitment: market.commitment.books,
+                        snapshot_interval: market.snapshot_interval,
+                        market: market.market.clone(),
+                        oracles: info.oracles(),
+                        skip_invalid: market.skip_invalid_orders,
+                        ..Default::default()
+                    }));
+
+                    // Asks
+                    self.extractors.push(Box::new(ObV2BooksPlugin {
+                        indicator_name: market.indicator_name("asks"),
+                        account: info.asks.clone(),
+                        program_id: market.program_id.clone(),
+                        units: info.units(),
+                        commitment: market.commitment.books,
+                        snapshot_interval: market.snapshot_interval,
+                        market: market.market.clone(),
+                        oracles: info.oracles(),
+                        skip_invalid: market.skip_invalid_orders,
+                        ..Default::default()
+                    }));
+
+                    // Two-sided market view (best bid/ask, mid, spread, depth)
+                    self.aggregators.push(Box::new(ObV2MarketViewPlugin {
+                        indicator_name: market.indicator_name("market"),
+                        bids_name: market.indicator_name("bids"),
+                        asks_name: market.indicator_name("asks"),
+                        depth: market.depth,
+                        ..Default::default()
+                    }));
+                }
+                PluginKind::Events => {
+                    // Events (Fill/Cancel)
+                    self.extractors.push(Box::new(ObV2EventsPlugin {
+                        indicator_name: market.indicator_name("events"),
+                        account: info.event_heap.clone(),
+                        program_id: market.program_id.clone(),
+                        units: info.units(),
+                        maker_fee: info.maker_fee,
+                        taker_fee: info.taker_fee,
+                        commitment: market.commitment.events,
+                        ..Default::default()
+                    }));
+                }
+                PluginKind::Transactions => {
+                    // Transactions (place_order, cancel_order)
+                    self.parsers.push(Box::new(ObV2TransactionsPlugin {
+                        indicator_name: market.indicator_name("txs"),
+                        account: market.market.clone(),
+                        program_id: market.program_id.clone(),
+                        units: info.units(),
+                        commitment: market.commitment.transactions,
+                    }));
+                }
+            }
+        }
+
+        // Our open orders accounts (resting orders, balances)
+        for account in market.open_orders.iter() {
+            self.extractors.push(Box::new(ObV2OpenOrdersPlugin {
+                indicator_name: market.indicator_name(&format!("oo_{}", account)),
+                account: account.clone(),
+                program_id: market.program_id.clone(),
+                units: info.units(),
+                commitment: market.commitment.open_orders,
+                ..Default::default()
+            }));
+        }
+
+        // Public and private (watched accounts) trade feeds, from fill events and fill logs
+        let watchlist = watchlist
+            .iter()
+            .chain(market.open_orders.iter())
+            .cloned()
+            .collect::<HashSet<_>>();
+        let sources = market
+            .plugins
+            .iter()
+            .filter_map(|plugin| match plugin {
+                PluginKind::Events => Some(market.indicator_name("events")),
+                PluginKind::Transactions => Some(market.indicator_name("txs")),
+                PluginKind::Books => None,
+            })
+            .collect::<Vec<_>>();
+        if !sources.is_empty() {
+            self.aggregators.push(Box::new(ObV2FillsPlugin {
+                indicator_name: market.indicator_name("fills"),
+                market: market.market.clone(),
+                sources: sources.clone(),
+                ..Default::default()
+            }));
+        }
+        if !watchlist.is_empty() && !sources.is_empty() {
+            self.aggregators.push(Box::new(ObV2MyFillsPlugin {
+                indicator_name: market.indicator_name("my_fills"),
+                market: market.market.clone(),
+                sources,
+                watchlist,
+                ..Default::default()
+            }));
+        }
+    }
+}
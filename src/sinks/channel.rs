@@ -0,0 +1,33 @@
+use tokio::sync::broadcast;
+
+use super::Sink;
+use crate::structs::IndicatorMsg;
+
+/// In-process sink, other tasks (e.g. the websocket server) subscribe to it
+#[derive(Clone, Debug)]
+pub struct ChannelSink {
+    pub sender: broadcast::Sender<IndicatorMsg>,
+}
+
+impl ChannelSink {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<IndicatorMsg> {
+        self.sender.subscribe()
+    }
+}
+
+impl Sink for ChannelSink {
+    fn name(&self) -> String {
+        "channel".to_string()
+    }
+
+    fn send(&mut self, msg: &IndicatorMsg) -> anyhow::Result<()> {
+        // No receivers is not an error, nobody is listening yet
+        let _ = self.sender.send(msg.clone());
+        Ok(())
+    }
+}
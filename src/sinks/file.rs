@@ -0,0 +1,165 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use super::{to_json, BackgroundWriter, LineOutput, Sink};
+use crate::structs::IndicatorMsg;
+use crate::units::Units;
+
+/// Append json lines to `path`, rotating to `path.1`, `path.2`, ... once it grows past `max_bytes`.
+/// Files are written on a background thread.
+#[derive(Debug)]
+pub struct RotatingFileSink {
+    pub path: PathBuf,
+    pub max_bytes: u64,
+    pub max_files: usize,
+    pub units: Units,
+    writer: BackgroundWriter,
+}
+
+impl RotatingFileSink {
+    pub fn new(
+        path: impl Into<PathBuf>,
+        max_bytes: u64,
+        max_files: usize,
+        units: Units,
+    ) -> anyhow::Result<Self> {
+        let file = RotatingFile::open(path.into(), max_bytes, max_files)?;
+        let path = file.path.clone();
+        let writer = BackgroundWriter::spawn(format!("file:{}", path.display()), file)?;
+
+        Ok(Self {
+            path,
+            max_bytes,
+            max_files,
+            units,
+            writer,
+        })
+    }
+}
+
+impl Sink for RotatingFileSink {
+    fn name(&self) -> String {
+        format!("file:{}", self.path.display())
+    }
+
+    fn send(&mut self, msg: &IndicatorMsg) -> anyhow::Result<()> {
+        let mut line = to_json(msg, self.units)?;
+        line.push(b'\n');
+        self.writer.write(line)
+    }
+}
+
+/// File side of `RotatingFileSink`, owned by its writer thread
+#[derive(Debug)]
+struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    writer: BufWriter<File>,
+    written: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_bytes: u64, max_files: usize) -> anyhow::Result<Self> {
+        if let Some(dir) = path.parent() {
+            if !dir.as_os_str().is_empty() {
+                fs::create_dir_all(dir)?;
+            }
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+
+        Ok(Self {
+            path,
+            max_bytes,
+            max_files,
+            writer: BufWriter::new(file),
+            written,
+        })
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+
+        if self.max_files > 0 {
+            let _ = fs::remove_file(self.rotated_path(self.max_files));
+            for index in (1..self.max_files).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.writer = BufWriter::new(file);
+        self.written = 0;
+
+        Ok(())
+    }
+}
+
+impl LineOutput for RotatingFile {
+    fn write_line(&mut self, line: &[u8]) -> anyhow::Result<()> {
+        if self.written > 0 && self.written + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+
+        self.writer.write_all(line)?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::structs::BotMsg;
+
+    #[test]
+    fn test_rotation_on_writer_thread() {
+        let dir = std::env::temp_dir().join(format!("geyser_sink_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("out.jsonl");
+
+        let msg = IndicatorMsg::new(
+            "ob_v2_sol_usdc_bids".to_string(),
+            1,
+            0,
+            BotMsg::Unimplemented,
+        );
+        let line_len = to_json(&msg, Units::All).unwrap().len() as u64 + 1;
+        {
+            let mut sink = RotatingFileSink::new(&path, line_len * 2, 2, Units::All).unwrap();
+            for _ in 0..5 {
+                sink.send(&msg).unwrap();
+            }
+            // Dropping the sink writes the queue out and flushes
+        }
+
+        let lines = |path: PathBuf| fs::read_to_string(path).unwrap().lines().count();
+        assert_eq!(lines(path.clone()), 1);
+        assert_eq!(lines(dir.join("out.jsonl.1")), 2);
+        assert_eq!(lines(dir.join("out.jsonl.2")), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
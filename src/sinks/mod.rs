@@ -0,0 +1,40 @@
+pub mod channel;
+pub mod file;
+pub mod stdout;
+pub mod writer;
+
+pub use channel::*;
+pub use file::*;
+pub use stdout::*;
+pub use writer::*;
+
+use crate::structs::{BotMsg, IndicatorMsg};
+use crate::units::Units;
+
+/// Destination of extracted data, every published `IndicatorMsg` goes to all sinks
+pub trait Sink: Send + Sync {
+    fn name(&self) -> String;
+
+    fn send(&mut self, msg: &IndicatorMsg) -> anyhow::Result<()>;
+}
+
+/// Json encoding of a message in the units of a sink
+pub fn to_json(msg: &IndicatorMsg, units: Units) -> anyhow::Result<Vec<u8>> {
+    match units {
+        Units::All => Ok(serde_json::to_vec(msg)?),
+        _ => Ok(serde_json::to_vec(&msg.to_value(units)?)?),
+    }
+}
+
+/// Forward plugin output to every sink, skipping empty results
+pub fn publish(sinks: &mut [Box<dyn Sink>], msg: IndicatorMsg) {
+    if let BotMsg::Unimplemented = msg.data {
+        return;
+    }
+
+    for sink in sinks.iter_mut() {
+        if let Err(e) = sink.send(&msg) {
+            tracing::error!("Sink {} error: {:?}", sink.name(), e);
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::io::{self, BufWriter, Stdout, Write};
+
+use super::{to_json, BackgroundWriter, LineOutput, Sink};
+use crate::structs::IndicatorMsg;
+use crate::units::Units;
+
+/// Write every message as one json line to stdout
+#[derive(Debug)]
+pub struct StdoutSink {
+    pub units: Units,
+    writer: BackgroundWriter,
+}
+
+impl StdoutSink {
+    pub fn new(units: Units) -> anyhow::Result<Self> {
+        Ok(Self {
+            units,
+            writer: BackgroundWriter::spawn("stdout".to_string(), BufWriter::new(io::stdout()))?,
+        })
+    }
+}
+
+impl LineOutput for BufWriter<Stdout> {
+    fn write_line(&mut self, line: &[u8]) -> anyhow::Result<()> {
+        self.write_all(line)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        Write::flush(self)?;
+        Ok(())
+    }
+}
+
+impl Sink for StdoutSink {
+    fn name(&self) -> String {
+        "stdout".to_string()
+    }
+
+    fn send(&mut self, msg: &IndicatorMsg) -> anyhow::Result<()> {
+        let mut line = to_json(msg, self.units)?;
+        line.push(b'\n');
+        self.writer.write(line)
+    }
+}
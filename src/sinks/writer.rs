@@ -0,0 +1,92 @@
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Lines queued per writer before messages are dropped
+pub const WRITER_CAPACITY: usize = 4096;
+
+/// Buffered lines are flushed at least this often
+pub const FLUSH_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Blocking output owned by a `BackgroundWriter` thread
+pub trait LineOutput: Send + 'static {
+    fn write_line(&mut self, line: &[u8]) -> anyhow::Result<()>;
+
+    fn flush(&mut self) -> anyhow::Result<()>;
+}
+
+/// Writes lines on a dedicated thread, so blocking io stays off the dispatch path.
+/// Flushes every `FLUSH_INTERVAL` and when dropped.
+#[derive(Debug)]
+pub struct BackgroundWriter {
+    name: String,
+    sender: Option<SyncSender<Vec<u8>>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl BackgroundWriter {
+    pub fn spawn(name: String, mut output: impl LineOutput) -> anyhow::Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(WRITER_CAPACITY);
+        let thread_name = name.clone();
+        let handle = thread::Builder::new()
+            .name(format!("sink-{}", name))
+            .spawn(move || {
+                let mut last_flush = Instant::now();
+                let mut dirty = false;
+                loop {
+                    match receiver.recv_timeout(FLUSH_INTERVAL) {
+                        Ok(line) => {
+                            if let Err(e) = output.write_line(&line) {
+                                tracing::error!("Sink {} write error: {:?}", thread_name, e);
+                            }
+                            dirty = true;
+                        }
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+
+                    if dirty && last_flush.elapsed() >= FLUSH_INTERVAL {
+                        if let Err(e) = output.flush() {
+                            tracing::error!("Sink {} flush error: {:?}", thread_name, e);
+                        }
+                        dirty = false;
+                        last_flush = Instant::now();
+                    }
+                }
+
+                if let Err(e) = output.flush() {
+                    tracing::error!("Sink {} flush error: {:?}", thread_name, e);
+                }
+            })?;
+
+        Ok(Self {
+            name,
+            sender: Some(sender),
+            handle: Some(handle),
+        })
+    }
+
+    /// Queue a line without blocking, a full queue drops it
+    pub fn write(&self, line: Vec<u8>) -> anyhow::Result<()> {
+        let Some(sender) = self.sender.as_ref() else {
+            anyhow::bail!("{}: writer stopped", self.name);
+        };
+        match sender.try_send(line) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                anyhow::bail!("{}: writer backlog full, message dropped", self.name)
+            }
+            Err(TrySendError::Disconnected(_)) => anyhow::bail!("{}: writer stopped", self.name),
+        }
+    }
+}
+
+impl Drop for BackgroundWriter {
+    fn drop(&mut self) {
+        // Closing the queue lets the thread write what is left and flush
+        self.sender.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
@@ -1,15 +1,20 @@
+use crate::error::GeyserError;
+use crate::units::{project_units, Fee, Price, Quantity, Units};
 use borsh::BorshDeserialize;
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use solana_sdk::{clock::UnixTimestamp, pubkey::Pubkey, signature::Signature};
+use solana_transaction_status::{
+    EncodedConfirmedTransactionWithStatusMeta, UiInnerInstructions, UiInstruction,
+    UiLoadedAddresses,
+};
+use std::collections::BTreeMap;
 use yellowstone_grpc_proto::{
-    geyser::{
-        SubscribeRequestFilterBlocksMeta, SubscribeUpdateAccount, SubscribeUpdateBlockMeta,
-        SubscribeUpdateTransaction,
-    },
+    geyser::{SubscribeUpdateAccount, SubscribeUpdateBlockMeta, SubscribeUpdateTransaction},
     solana::storage::confirmed_block::{Message, TransactionStatusMeta},
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub struct Account {
     pub is_startup: bool,
@@ -22,32 +27,42 @@ pub struct Account {
     pub data: Vec<u8>,
     pub write_version: u64,
     pub txn_signature: String,
+    /// Block time of the slot, if its block meta was already received
+    pub block_time: Option<UnixTimestamp>,
 }
 
-impl From<SubscribeUpdateAccount> for Account {
-    fn from(
+impl TryFrom<SubscribeUpdateAccount> for Account {
+    type Error = GeyserError;
+
+    fn try_from(
         SubscribeUpdateAccount {
             is_startup,
             slot,
             account,
         }: SubscribeUpdateAccount,
-    ) -> Self {
-        let account = account.expect("should be defined");
-        Self {
+    ) -> Result<Self, Self::Error> {
+        let account = account.ok_or(GeyserError::MissingField("account"))?;
+        Ok(Self {
             is_startup,
             slot,
-            pubkey: Pubkey::try_from(account.pubkey).expect("valid pubkey"),
+            pubkey: to_pubkey(&account.pubkey)?,
             lamports: account.lamports,
-            owner: Pubkey::try_from(account.owner).expect("valid pubkey"),
+            owner: to_pubkey(&account.owner)?,
             executable: account.executable,
             rent_epoch: account.rent_epoch,
             data: account.data,
             write_version: account.write_version,
             txn_signature: bs58::encode(account.txn_signature.unwrap_or_default()).into_string(),
-        }
+            block_time: None,
+        })
     }
 }
 
+fn to_pubkey(bytes: &[u8]) -> Result<Pubkey, GeyserError> {
+    Pubkey::try_from_slice(bytes)
+        .map_err(|_| GeyserError::InvalidPubkey(bs58::encode(bytes).into_string()))
+}
+
 #[derive(Debug, Clone)]
 pub struct MessageTransaction {
     pub signature: Signature,
@@ -56,23 +71,34 @@ pub struct MessageTransaction {
     pub meta: TransactionStatusMeta,
     pub index: u64,
     pub slot: u64,
+    /// Filled from the block meta of the slot
+    pub block_time: Option<UnixTimestamp>,
 }
 
-impl From<SubscribeUpdateTransaction> for MessageTransaction {
-    fn from(SubscribeUpdateTransaction { transaction, slot }: SubscribeUpdateTransaction) -> Self {
-        let transaction = transaction.expect("transaction should be defined");
-        let meta = transaction.meta.expect("meta should be defined");
-        let tx_body = transaction.transaction.expect("tx body should be defined");
-        let message = tx_body.message.expect("message should be defined");
+impl TryFrom<SubscribeUpdateTransaction> for MessageTransaction {
+    type Error = GeyserError;
 
-        Self {
+    fn try_from(
+        SubscribeUpdateTransaction { transaction, slot }: SubscribeUpdateTransaction,
+    ) -> Result<Self, Self::Error> {
+        let transaction = transaction.ok_or(GeyserError::MissingField("transaction"))?;
+        let meta = transaction.meta.ok_or(GeyserError::MissingField("meta"))?;
+        let tx_body = transaction
+            .transaction
+            .ok_or(GeyserError::MissingField("tx body"))?;
+        let message = tx_body
+            .message
+            .ok_or(GeyserError::MissingField("message"))?;
+
+        Ok(Self {
             signature: Signature::try_from(transaction.signature).unwrap_or_default(),
             is_vote: transaction.is_vote,
             message,
             meta,
             index: transaction.index,
             slot,
-        }
+            block_time: None,
+        })
     }
 }
 
@@ -80,43 +106,39 @@ impl MessageTransaction {
     pub fn parse_message(
         &self,
         loaded_addresses: &[String],
-    ) -> (Vec<String>, Vec<ParsedInstruction>) {
+    ) -> Result<(Vec<String>, Vec<ParsedInstruction>), GeyserError> {
         let mut keys = self
             .message
             .account_keys
             .iter()
-            .map(|pk| Pubkey::try_from_slice(pk).unwrap().to_string())
-            .collect_vec();
+            .map(|pk| to_pubkey(pk).map(|pk| pk.to_string()))
+            .collect::<Result<Vec<String>, GeyserError>>()?;
         keys.extend_from_slice(loaded_addresses);
         let instructions = self
             .message
             .instructions
             .iter()
-            .map(|instruction| ParsedInstruction {
-                program_id: keys[instruction.program_id_index as usize].clone(),
-                accounts: instruction.accounts.clone(),
-                data: instruction.data.clone(),
+            .map(|instruction| {
+                Ok(ParsedInstruction {
+                    program_id: account_key(&keys, instruction.program_id_index)?,
+                    accounts: instruction.accounts.clone(),
+                    data: instruction.data.clone(),
+                })
             })
-            .collect_vec();
-        (keys, instructions)
+            .collect::<Result<Vec<ParsedInstruction>, GeyserError>>()?;
+        Ok((keys, instructions))
     }
 
-    pub fn to_parsed_transaction(&self) -> ParsedTransaction {
-        let loaded_addresses = [
-            self.meta
-                .loaded_writable_addresses
-                .iter()
-                .map(|x| Pubkey::try_from_slice(x).unwrap().to_string())
-                .collect_vec(),
-            self.meta
-                .loaded_readonly_addresses
-                .iter()
-                .map(|x| Pubkey::try_from_slice(x).unwrap().to_string())
-                .collect_vec(),
-        ]
-        .concat();
+    pub fn to_parsed_transaction(&self) -> Result<ParsedTransaction, GeyserError> {
+        let loaded_addresses = self
+            .meta
+            .loaded_writable_addresses
+            .iter()
+            .chain(self.meta.loaded_readonly_addresses.iter())
+            .map(|x| to_pubkey(x).map(|pk| pk.to_string()))
+            .collect::<Result<Vec<String>, GeyserError>>()?;
 
-        let (keys, instructions) = self.parse_message(&loaded_addresses);
+        let (keys, instructions) = self.parse_message(&loaded_addresses)?;
         let is_err = self.meta.err.is_some();
         let logs = self.meta.log_messages.clone();
 
@@ -133,28 +155,119 @@ impl MessageTransaction {
                 Some(inner_ixs) => inner_ixs
                     .instructions
                     .iter()
-                    .map(|ii| ParsedInstruction {
-                        program_id: keys[ii.program_id_index as usize].clone(),
-                        accounts: ii.accounts.clone(),
-                        data: ii.data.clone(),
+                    .map(|ii| {
+                        Ok(ParsedInstruction {
+                            program_id: account_key(&keys, ii.program_id_index)?,
+                            accounts: ii.accounts.clone(),
+                            data: ii.data.clone(),
+                        })
                     })
-                    .collect::<Vec<ParsedInstruction>>(),
+                    .collect::<Result<Vec<ParsedInstruction>, GeyserError>>()?,
                 None => vec![],
             };
 
             inner_instructions.push(inner_ixs);
         }
 
-        ParsedTransaction {
+        Ok(ParsedTransaction {
             slot: self.slot,
-            block_time: None,
+            block_time: self.block_time,
+            index: Some(self.index),
             signature: self.signature.to_string(),
             instructions,
             inner_instructions,
             accounts: keys,
             logs,
             is_err,
+        })
+    }
+}
+
+fn account_key(keys: &[String], index: u32) -> Result<String, GeyserError> {
+    keys.get(index as usize)
+        .cloned()
+        .ok_or(GeyserError::AccountIndex(index as usize))
+}
+
+impl ParsedTransaction {
+    /// Build from a `getTransaction` response, requested with base64 encoding
+    pub fn from_rpc(
+        signature: &str,
+        EncodedConfirmedTransactionWithStatusMeta {
+            slot,
+            transaction,
+            block_time,
+        }: EncodedConfirmedTransactionWithStatusMeta,
+    ) -> anyhow::Result<Self> {
+        let tx = transaction
+            .transaction
+            .decode()
+            .ok_or(anyhow::anyhow!("tx {}: undecodable transaction", signature))?;
+        let meta = transaction
+            .meta
+            .ok_or(anyhow::anyhow!("tx {}: meta should be defined", signature))?;
+
+        let mut accounts = tx
+            .message
+            .static_account_keys()
+            .iter()
+            .map(|pk| pk.to_string())
+            .collect_vec();
+        if let Some(loaded) = Option::<UiLoadedAddresses>::from(meta.loaded_addresses) {
+            accounts.extend(loaded.writable);
+            accounts.extend(loaded.readonly);
         }
+
+        let instructions = tx
+            .message
+            .instructions()
+            .iter()
+            .map(|ix| {
+                Ok(ParsedInstruction {
+                    program_id: account_key(&accounts, ix.program_id_index as u32)?,
+                    accounts: ix.accounts.clone(),
+                    data: ix.data.clone(),
+                })
+            })
+            .collect::<Result<Vec<ParsedInstruction>, GeyserError>>()?;
+
+        let mut inner_instructions: Vec<Vec<ParsedInstruction>> = vec![vec![]; instructions.len()];
+        for inner_ixs in
+            Option::<Vec<UiInnerInstructions>>::from(meta.inner_instructions).unwrap_or_default()
+        {
+            let parsed = inner_ixs
+                .instructions
+                .into_iter()
+                .filter_map(|ii| match ii {
+                    UiInstruction::Compiled(ii) => Some(ii),
+                    UiInstruction::Parsed(_) => None,
+                })
+                .map(|ii| {
+                    Ok(ParsedInstruction {
+                        program_id: account_key(&accounts, ii.program_id_index as u32)?,
+                        accounts: ii.accounts,
+                        data: bs58::decode(ii.data).into_vec().unwrap_or_default(),
+                    })
+                })
+                .collect::<Result<Vec<ParsedInstruction>, GeyserError>>()?;
+
+            if let Some(entry) = inner_instructions.get_mut(inner_ixs.index as usize) {
+                *entry = parsed;
+            }
+        }
+
+        Ok(Self {
+            slot,
+            block_time,
+            // Not part of rpc responses
+            index: None,
+            signature: signature.to_string(),
+            instructions,
+            inner_instructions,
+            accounts,
+            logs: Option::<Vec<String>>::from(meta.log_messages).unwrap_or_default(),
+            is_err: meta.err.is_some(),
+        })
     }
 }
 
@@ -162,6 +275,8 @@ impl MessageTransaction {
 pub struct ParsedTransaction {
     pub slot: u64,
     pub block_time: Option<UnixTimestamp>,
+    /// Position of the transaction in its block
+    pub index: Option<u64>,
     pub instructions: Vec<ParsedInstruction>,
     pub inner_instructions: Vec<Vec<ParsedInstruction>>,
     pub logs: Vec<String>,
@@ -180,7 +295,7 @@ pub struct ParsedInstruction {
 #[derive(Debug, Clone)]
 pub struct ParsedBlock {
     pub slot: u64,
-    pub block_time: i64,
+    pub block_time: Option<UnixTimestamp>,
 }
 
 impl From<SubscribeUpdateBlockMeta> for ParsedBlock {
@@ -189,57 +304,884 @@ impl From<SubscribeUpdateBlockMeta> for ParsedBlock {
             slot, block_time, ..
         }: SubscribeUpdateBlockMeta,
     ) -> Self {
-        let block_time = match block_time {
-            Some(time) => time.timestamp,
-            None => 0,
-        };
-
-        Self { slot, block_time }
+        Self {
+            slot,
+            block_time: block_time.map(|time| time.timestamp),
+        }
     }
 }
 
-#[derive(Debug)]
+/// Wire format version of `IndicatorMsg`, bump when fields are removed or change meaning
+/// 2: transactions plugins emit `ob_v2_transaction` instead of `ob_v2_events`
+/// 3: events and my fills are wrapped in `ObV2Envelope`
+/// 4: `slot_status` upgrades of `processed_with_upgrades` indicators
+/// 5: prices, amounts and fees are exact decimal strings
+/// 6: raw `_lots` and `_native` fields next to UI values, consumers pick with `units`
+pub const SCHEMA_VERSION: u16 = 6;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct OpenBook {
     pub owner: String,
+    #[serde(with = "u128_string")]
     pub order_id: u128,
     pub is_buy: bool,
-    pub price: f64,
-    pub amount: f64,
+    pub price: Price,
+    #[serde(default)]
+    pub price_lots: i64,
+    pub amount: Quantity,
+    #[serde(default)]
+    pub amount_lots: i64,
+    #[serde(default)]
+    pub amount_native: u64,
+    /// Price follows the market oracle, `price` is the effective price at the update
+    #[serde(default)]
+    pub is_oracle_pegged: bool,
+    /// Worst price a pegged order still matches at, none without limit
+    #[serde(default)]
+    pub peg_limit: Option<Price>,
+    #[serde(default)]
+    pub peg_limit_lots: Option<i64>,
+    /// Seconds the order lives after it was placed, 0 until cancelled
+    #[serde(default)]
+    pub time_in_force: u16,
+    /// Unix time the order expires at, none if it doesn't
+    #[serde(default)]
+    pub expiry_timestamp: Option<u64>,
+    /// False for orders the program skips when matching (expired, pegged past their limit)
+    #[serde(default)]
+    pub is_valid: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ObV2Fill {
     pub taker: String,
     pub maker: String,
+    /// Taker side
     pub is_buy: bool,
-    pub price: f64,
-    pub amount: f64,
+    pub price: Price,
+    #[serde(default)]
+    pub price_lots: i64,
+    pub amount: Quantity,
+    #[serde(default)]
+    pub amount_lots: i64,
+    #[serde(default)]
+    pub amount_native: u64,
+    /// Maker client order id
     pub order_id: u64,
+    #[serde(default)]
+    pub taker_client_order_id: u64,
+    /// Quote fees, a negative maker fee is a rebate
+    #[serde(default)]
+    pub maker_fee: Fee,
+    #[serde(default)]
+    pub maker_fee_native: i64,
+    #[serde(default)]
+    pub taker_fee: Fee,
+    #[serde(default)]
+    pub taker_fee_native: i64,
+    /// Market sequence number of the fill
+    #[serde(default)]
+    pub seq_num: u64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ObV2Cancel {
     pub seq_num: u64,
     pub owner: String,
     pub is_buy: bool,
-    pub amount: f64,
+    pub amount: Quantity,
+    #[serde(default)]
+    pub amount_lots: i64,
+    #[serde(default)]
+    pub amount_native: u64,
+}
+
+/// `OpenOrdersPositionLog`, balances of an open orders account after an instruction
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObV2PositionLog {
+    pub owner: String,
+    pub market: String,
+    pub open_orders_account_num: u32,
+    pub position: ObV2Position,
+    /// Lifetime volumes in quote
+    pub maker_volume: Quantity,
+    #[serde(default, with = "u128_string")]
+    pub maker_volume_native: u128,
+    pub taker_volume: Quantity,
+    #[serde(default, with = "u128_string")]
+    pub taker_volume_native: u128,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObV2SettleFunds {
+    pub open_orders_account: String,
+    pub base_amount: Quantity,
+    #[serde(default)]
+    pub base_amount_native: u64,
+    pub quote_amount: Quantity,
+    #[serde(default)]
+    pub quote_amount_native: u64,
+    pub referrer_rebate: Quantity,
+    #[serde(default)]
+    pub referrer_rebate_native: u64,
+    pub referrer: Option<String>,
+}
+
+/// `TotalOrderFillEvent`, everything a taker order matched
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObV2TotalOrderFill {
+    pub taker: String,
+    pub is_buy: bool,
+    pub base_amount: Quantity,
+    #[serde(default)]
+    pub base_amount_native: u64,
+    pub quote_amount: Quantity,
+    #[serde(default)]
+    pub quote_amount_native: u64,
+    pub fees: Quantity,
+    #[serde(default)]
+    pub fees_native: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObV2Deposit {
+    pub open_orders_account: String,
+    pub signer: String,
+    pub base_amount: Quantity,
+    #[serde(default)]
+    pub base_amount_native: u64,
+    pub quote_amount: Quantity,
+    #[serde(default)]
+    pub quote_amount_native: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObV2SetDelegate {
+    pub open_orders_account: String,
+    pub delegate: Option<String>,
+}
+
+/// `MarketMetaDataLog`, emitted on market creation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObV2MarketMetaData {
+    pub market: String,
+    pub name: String,
+    pub base_mint: String,
+    pub quote_mint: String,
+    pub base_decimals: u8,
+    pub quote_decimals: u8,
+    pub base_lot_size: i64,
+    pub quote_lot_size: i64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObV2SweepFees {
+    pub market: String,
+    pub amount: Quantity,
+    #[serde(default)]
+    pub amount_native: u64,
+    pub receiver: String,
+}
+
+/// Program log the feed can't decode, kept raw
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObV2UnknownLog {
+    pub discriminator: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
 pub enum ObV2Event {
     Fill(ObV2Fill),
     Cancel(ObV2Cancel),
+    Position(ObV2PositionLog),
+    SettleFunds(ObV2SettleFunds),
+    TotalOrderFill(ObV2TotalOrderFill),
+    Deposit(ObV2Deposit),
+    SetDelegate(ObV2SetDelegate),
+    MarketMetaData(ObV2MarketMetaData),
+    SweepFees(ObV2SweepFees),
+    Unknown(ObV2UnknownLog),
+}
+
+impl ObV2Event {
+    /// Market named by the event itself, most events only have it in their instruction
+    pub fn market(&self) -> Option<&str> {
+        match self {
+            ObV2Event::Position(log) => Some(&log.market),
+            ObV2Event::MarketMetaData(log) => Some(&log.market),
+            ObV2Event::SweepFees(log) => Some(&log.market),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ObV2Role {
+    Maker,
+    Taker,
+}
+
+/// Fill of a watched account, seen from its side of the trade
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObV2MyFill {
+    /// Watched account the fill belongs to
+    pub account: String,
+    pub counterparty: String,
+    pub role: ObV2Role,
+    pub is_buy: bool,
+    pub price: Price,
+    #[serde(default)]
+    pub price_lots: i64,
+    pub amount: Quantity,
+    #[serde(default)]
+    pub amount_lots: i64,
+    #[serde(default)]
+    pub amount_native: u64,
+    /// Quote fee paid, negative for maker rebates
+    pub fee: Fee,
+    #[serde(default)]
+    pub fee_native: i64,
+    pub client_order_id: u64,
+    pub seq_num: u64,
+}
+
+/// Order arguments of a place instruction, prices and sizes in UI units next to raw lots
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObV2OrderArgs {
+    pub is_buy: bool,
+    pub price_lots: i64,
+    pub price: Price,
+    pub max_base_lots: i64,
+    pub amount: Quantity,
+    pub max_quote_lots_including_fees: i64,
+    pub client_order_id: u64,
+    pub order_type: String,
+    /// 0 means no expiry
+    pub expiry_timestamp: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObV2MultipleOrdersArgs {
+    pub order_type: String,
+    pub bids: Vec<ObV2OrderArgs>,
+    pub asks: Vec<ObV2OrderArgs>,
+    pub limit: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "args", rename_all = "snake_case")]
+pub enum ObV2InstructionKind {
+    PlaceOrder(ObV2OrderArgs),
+    PlaceTakeOrder(ObV2OrderArgs),
+    PlaceOrders(ObV2MultipleOrdersArgs),
+    CancelAllAndPlaceOrders(ObV2MultipleOrdersArgs),
+    CancelOrder {
+        #[serde(with = "u128_string")]
+        order_id: u128,
+    },
+    SettleFunds,
+    ConsumeEvents {
+        limit: u64,
+    },
+    Unknown {
+        discriminator: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObV2AccountMeta {
+    pub name: String,
+    pub pubkey: String,
+}
+
+/// Decoded openbook v2 instruction of a transaction
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObV2Instruction {
+    /// Position of the top-level instruction
+    pub index: usize,
+    /// Position among the inner instructions of `index`, none for top-level ones
+    pub inner_index: Option<usize>,
+    pub instruction: ObV2InstructionKind,
+    pub accounts: Vec<ObV2AccountMeta>,
+}
+
+/// Openbook v2 instructions of one transaction and the events they generated
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObV2TransactionData {
+    pub signature: String,
+    pub instructions: Vec<ObV2Instruction>,
+    pub events: Vec<ObV2Envelope<ObV2Event>>,
+    /// Logs were cut by the runtime, only events emitted through `emit_cpi!` are complete
+    #[serde(default)]
+    pub is_log_truncated: bool,
+}
+
+/// Aggregated L2 price level, `amount` 0 means the level was removed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObV2Level {
+    pub price_lots: i64,
+    pub price: Price,
+    pub amount: Quantity,
+    #[serde(default)]
+    pub amount_lots: i64,
+    #[serde(default)]
+    pub amount_native: u64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ObV2BooksData {
-    pub best: Option<f64>,
+    /// Sequence number of the message, snapshots and diffs of a side share one counter
+    #[serde(default)]
+    pub seq: u64,
+    pub best: Option<Price>,
+    #[serde(default)]
+    pub best_lots: Option<i64>,
     pub books: Vec<OpenBook>,
+    #[serde(default)]
+    pub levels: Vec<ObV2Level>,
+}
+
+/// Changes of one book side since the previous account update, keyed by order id
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObV2BookDiff {
+    /// Follows the previous snapshot or diff by one, a jump means a missed diff
+    #[serde(default)]
+    pub seq: u64,
+    /// `seq` of the snapshot the diff applies on top of
+    #[serde(default)]
+    pub snapshot_seq: u64,
+    pub best: Option<Price>,
+    #[serde(default)]
+    pub best_lots: Option<i64>,
+    pub added: Vec<OpenBook>,
+    pub removed: Vec<OpenBook>,
+    /// Orders with a new amount or price, carrying the new values
+    pub changed: Vec<OpenBook>,
+    /// Changed L2 levels only
+    pub levels: Vec<ObV2Level>,
+}
+
+/// Two-sided view of one market, joined from its bids and asks books
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObV2MarketView {
+    pub best_bid: Option<Price>,
+    #[serde(default)]
+    pub best_bid_lots: Option<i64>,
+    pub best_ask: Option<Price>,
+    #[serde(default)]
+    pub best_ask_lots: Option<i64>,
+    pub mid: Option<Price>,
+    /// Lossy, for display only. Negative on a crossed book.
+    pub spread_bps: Option<f64>,
+    pub is_crossed: bool,
+    /// Top levels, best first
+    pub bids: Vec<ObV2Level>,
+    pub asks: Vec<ObV2Level>,
+    /// Slot of the last applied update of each side
+    pub bids_slot: u64,
+    pub asks_slot: u64,
+    /// Slots between the sides' last updates, a quiet side lags without being stale
+    #[serde(default)]
+    pub slot_skew: u64,
+}
+
+/// Where an event comes from. Events of a slot are ordered by
+/// (tx_index, ix_index, inner_ix_index, seq), which also identifies them for dedupe.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObV2Envelope<T> {
+    pub slot: u64,
+    pub block_time: Option<UnixTimestamp>,
+    /// Transaction emitting the event, or last writing the account for account events
+    pub signature: Option<String>,
+    /// Position of the transaction in its block, unknown for backfilled transactions
+    pub tx_index: Option<u64>,
+    /// Top-level and inner instruction emitting the event
+    pub ix_index: Option<usize>,
+    pub inner_ix_index: Option<usize>,
+    /// Position of the event in its transaction or account update
+    pub seq: u32,
+    pub event: T,
+}
+
+impl<T> ObV2Envelope<T> {
+    /// Same origin, other event (e.g. a fill seen from one side)
+    pub fn with_event<U>(&self, event: U) -> ObV2Envelope<U> {
+        ObV2Envelope {
+            slot: self.slot,
+            block_time: self.block_time,
+            signature: self.signature.clone(),
+            tx_index: self.tx_index,
+            ix_index: self.ix_index,
+            inner_ix_index: self.inner_ix_index,
+            seq: self.seq,
+            event,
+        }
+    }
+}
+
+/// Resting order of a tracked open orders account
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObV2OpenOrder {
+    #[serde(with = "u128_string")]
+    pub order_id: u128,
+    pub client_order_id: u64,
+    pub is_buy: bool,
+    pub is_oracle_pegged: bool,
+    /// Price the funds were locked at
+    pub locked_price: Price,
+    #[serde(default)]
+    pub locked_price_lots: i64,
+}
+
+/// Balances of a tracked open orders account
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObV2Position {
+    /// Base size of resting bids and asks
+    pub bids_base: Quantity,
+    #[serde(default)]
+    pub bids_base_lots: i64,
+    pub asks_base: Quantity,
+    #[serde(default)]
+    pub asks_base_lots: i64,
+    /// Funds locked in resting orders (quote includes locked maker fees)
+    pub base_locked: Quantity,
+    #[serde(default)]
+    pub base_locked_native: u64,
+    pub quote_locked: Quantity,
+    #[serde(default)]
+    pub quote_locked_native: u64,
+    /// Settled funds, free to withdraw
+    pub base_free: Quantity,
+    #[serde(default)]
+    pub base_free_native: u64,
+    pub quote_free: Quantity,
+    #[serde(default)]
+    pub quote_free_native: u64,
+    pub locked_maker_fees: Quantity,
+    #[serde(default)]
+    pub locked_maker_fees_native: u64,
+    pub referrer_rebates: Quantity,
+    #[serde(default)]
+    pub referrer_rebates_native: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObV2OpenOrdersData {
+    pub owner: String,
+    pub market: String,
+    pub orders: Vec<ObV2OpenOrder>,
+    pub position: ObV2Position,
+}
+
+/// Changes of an open orders account since its previous update
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObV2OpenOrdersDiff {
+    pub added: Vec<ObV2OpenOrder>,
+    pub removed: Vec<ObV2OpenOrder>,
+    /// New balances, none if unchanged
+    pub position: Option<ObV2Position>,
+}
+
+/// Updates between the two slots may have been missed (geyser disconnected).
+/// Extractors follow up with a full snapshot, parsers with backfilled transactions.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObV2Gap {
+    pub from_slot: u64,
+    pub to_slot: u64,
+}
+
+/// Status change of a slot an indicator already published at processed.
+/// Rolled back slots were on a dead fork, their messages must be discarded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ObV2SlotStatus {
+    Confirmed,
+    Finalized,
+    RolledBack,
+}
+
+/// Skipped updates and items per plugin since startup, published under `errors` when they change
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObV2Errors {
+    pub counts: BTreeMap<String, u64>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
 pub enum BotMsg {
     ObV2Books(ObV2BooksData),
-    ObV2Events(Vec<ObV2Event>),
+    ObV2BookDiff(ObV2BookDiff),
+    ObV2Market(ObV2MarketView),
+    ObV2Events(Vec<ObV2Envelope<ObV2Event>>),
+    ObV2Transaction(ObV2TransactionData),
+    ObV2OpenOrders(ObV2OpenOrdersData),
+    ObV2OpenOrdersDiff(ObV2OpenOrdersDiff),
+    ObV2MyFills(Vec<ObV2Envelope<ObV2MyFill>>),
+    Gap(ObV2Gap),
+    SlotStatus(ObV2SlotStatus),
+    Errors(ObV2Errors),
     Unimplemented,
 }
+
+/// Plugin output published to downstream consumers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndicatorMsg {
+    /// Always `SCHEMA_VERSION` of the producer
+    pub version: u16,
+    /// Plugin (indicator) name, e.g. `ob_v2_sol_usdc_bids`
+    pub name: String,
+    pub slot: u64,
+    /// Account write version, 0 for transactions and initial loads
+    pub write_version: u64,
+    pub data: BotMsg,
+}
+
+impl IndicatorMsg {
+    pub fn new(name: String, slot: u64, write_version: u64, data: BotMsg) -> Self {
+        Self {
+            version: SCHEMA_VERSION,
+            name,
+            slot,
+            write_version,
+            data,
+        }
+    }
+
+    /// Message as sent to a consumer asking for `units`
+    pub fn to_value(&self, units: Units) -> serde_json::Result<serde_json::Value> {
+        let mut value = serde_json::to_value(self)?;
+        project_units(&mut value, units);
+        Ok(value)
+    }
+}
+
+/// u128 order ids don't fit json numbers of most consumers, keep them as decimal strings
+mod u128_string {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &u128, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u128, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn price(s: &str) -> Price {
+        s.parse().unwrap()
+    }
+
+    fn quantity(s: &str) -> Quantity {
+        s.parse().unwrap()
+    }
+
+    fn envelope<T>(seq: u32, event: T) -> ObV2Envelope<T> {
+        ObV2Envelope {
+            slot: 250_000_001,
+            block_time: Some(1_700_000_000),
+            signature: Some("sig".to_string()),
+            tx_index: Some(12),
+            ix_index: Some(2),
+            inner_ix_index: None,
+            seq,
+            event,
+        }
+    }
+
+    fn samples() -> Vec<IndicatorMsg> {
+        vec![
+            IndicatorMsg::new(
+                "ob_v2_sol_usdc_bids".to_string(),
+                250_000_000,
+                1_024,
+                BotMsg::ObV2Books(ObV2BooksData {
+                    seq: 1,
+                    best: Some(price("101.25")),
+                    best_lots: Some(101_250),
+                    books: vec![OpenBook {
+                        owner: "53v47CBoaKwoM8tSEDN4oNyCc2ZJenDeuhMJTEw7fL2M".to_string(),
+                        order_id: u128::MAX - 1,
+                        is_buy: true,
+                        price: price("101.25"),
+                        price_lots: 101_250,
+                        amount: quantity("0.5"),
+                        amount_lots: 500,
+                        amount_native: 500_000_000,
+                        is_oracle_pegged: true,
+                        peg_limit: Some(price("102")),
+                        peg_limit_lots: Some(102_000),
+                        time_in_force: 60,
+                        expiry_timestamp: Some(1_700_000_060),
+                        is_valid: true,
+                    }],
+                    levels: vec![ObV2Level {
+                        price_lots: 101_250,
+                        price: price("101.25"),
+                        amount: quantity("0.5"),
+                        amount_lots: 500,
+                        amount_native: 500_000_000,
+                    }],
+                }),
+            ),
+            IndicatorMsg::new(
+                "ob_v2_sol_usdc_events".to_string(),
+                250_000_001,
+                0,
+                BotMsg::ObV2Events(vec![
+                    envelope(
+                        0,
+                        ObV2Event::Fill(ObV2Fill {
+                            taker: "taker".to_string(),
+                            maker: "maker".to_string(),
+                            is_buy: false,
+                            price: price("99.5"),
+                            price_lots: 99_500,
+                            amount: quantity("1.0"),
+                            amount_lots: 1_000,
+                            amount_native: 1_000_000_000,
+                            order_id: 42,
+                            taker_client_order_id: 43,
+                            maker_fee: "-0.0002".parse().unwrap(),
+                            maker_fee_native: -200,
+                            taker_fee: "0.0004".parse().unwrap(),
+                            taker_fee_native: 400,
+                            seq_num: 1_000,
+                        }),
+                    ),
+                    envelope(
+                        1,
+                        ObV2Event::Cancel(ObV2Cancel {
+                            seq_num: 7,
+                            owner: "owner".to_string(),
+                            is_buy: true,
+                            amount: quantity("2.5"),
+                            amount_lots: 2_500,
+                            amount_native: 2_500_000_000,
+                        }),
+                    ),
+                ]),
+            ),
+            IndicatorMsg::new(
+                "ob_v2_sol_usdc_oo_owner".to_string(),
+                250_000_002,
+                12,
+                BotMsg::ObV2OpenOrdersDiff(ObV2OpenOrdersDiff {
+                    added: vec![ObV2OpenOrder {
+                        order_id: u128::MAX,
+                        client_order_id: 7,
+                        is_buy: false,
+                        is_oracle_pegged: true,
+                        locked_price: price("102.0"),
+                        locked_price_lots: 102_000,
+                    }],
+                    removed: vec![],
+                    position: Some(ObV2Position {
+                        bids_base: quantity("0.0"),
+                        bids_base_lots: 0,
+                        asks_base: quantity("1.5"),
+                        asks_base_lots: 1_500,
+                        base_locked: quantity("1.5"),
+                        base_locked_native: 1_500_000_000,
+                        quote_locked: quantity("0.0"),
+                        quote_locked_native: 0,
+                        base_free: quantity("0.25"),
+                        base_free_native: 250_000_000,
+                        quote_free: quantity("10.0"),
+                        quote_free_native: 10_000_000,
+                        locked_maker_fees: quantity("0.0"),
+                        locked_maker_fees_native: 0,
+                        referrer_rebates: quantity("0.01"),
+                        referrer_rebates_native: 10_000,
+                    }),
+                }),
+            ),
+            IndicatorMsg::new(
+                "ob_v2_sol_usdc_my_fills".to_string(),
+                250_000_001,
+                0,
+                BotMsg::ObV2MyFills(vec![envelope(
+                    0,
+                    ObV2MyFill {
+                        account: "maker".to_string(),
+                        counterparty: "taker".to_string(),
+                        role: ObV2Role::Maker,
+                        is_buy: true,
+                        price: price("99.5"),
+                        price_lots: 99_500,
+                        amount: quantity("1.0"),
+                        amount_lots: 1_000,
+                        amount_native: 1_000_000_000,
+                        fee: "-0.0002".parse().unwrap(),
+                        fee_native: -200,
+                        client_order_id: 42,
+                        seq_num: 1_000,
+                    },
+                )]),
+            ),
+            IndicatorMsg::new(
+                "ob_v2_sol_usdc_txs".to_string(),
+                250_000_003,
+                0,
+                BotMsg::ObV2Transaction(ObV2TransactionData {
+                    signature: "sig".to_string(),
+                    instructions: vec![
+                        ObV2Instruction {
+                            index: 0,
+                            inner_index: None,
+                            instruction: ObV2InstructionKind::CancelOrder {
+                                order_id: u128::MAX,
+                            },
+                            accounts: vec![ObV2AccountMeta {
+                                name: "market".to_string(),
+                                pubkey: "CFSMrBssNG8Ud1edW59jNLnq2cwrQ9uY5cM3wXmqRJj3".to_string(),
+                            }],
+                        },
+                        ObV2Instruction {
+                            index: 1,
+                            inner_index: Some(0),
+                            instruction: ObV2InstructionKind::PlaceOrders(ObV2MultipleOrdersArgs {
+                                order_type: "PostOnly".to_string(),
+                                bids: vec![ObV2OrderArgs {
+                                    is_buy: true,
+                                    price_lots: 101_000,
+                                    price: price("101.0"),
+                                    max_base_lots: 0,
+                                    amount: quantity("0.0"),
+                                    max_quote_lots_including_fees: 1_010_000,
+                                    client_order_id: 0,
+                                    order_type: String::new(),
+                                    expiry_timestamp: 0,
+                                }],
+                                asks: vec![],
+                                limit: 10,
+                            }),
+                            accounts: vec![],
+                        },
+                        ObV2Instruction {
+                            index: 2,
+                            inner_index: None,
+                            instruction: ObV2InstructionKind::SettleFunds,
+                            accounts: vec![],
+                        },
+                    ],
+                    events: vec![
+                        envelope(
+                            0,
+                            ObV2Event::SettleFunds(ObV2SettleFunds {
+                                open_orders_account: "owner".to_string(),
+                                base_amount: quantity("1.5"),
+                                base_amount_native: 1_500_000_000,
+                                quote_amount: quantity("0.0"),
+                                quote_amount_native: 0,
+                                referrer_rebate: quantity("0.0"),
+                                referrer_rebate_native: 0,
+                                referrer: None,
+                            }),
+                        ),
+                        envelope(
+                            1,
+                            ObV2Event::Unknown(ObV2UnknownLog {
+                                discriminator: vec![1, 2, 3, 4, 5, 6, 7, 8],
+                                data: vec![9, 10],
+                            }),
+                        ),
+                    ],
+                    is_log_truncated: true,
+                }),
+            ),
+            IndicatorMsg::new(
+                "ob_v2_sol_usdc_bids".to_string(),
+                1,
+                0,
+                BotMsg::SlotStatus(ObV2SlotStatus::RolledBack),
+            ),
+            IndicatorMsg::new(
+                "errors".to_string(),
+                1,
+                0,
+                BotMsg::Errors(ObV2Errors {
+                    counts: BTreeMap::from([("ob_v2_sol_usdc_events".to_string(), 2)]),
+                }),
+            ),
+            IndicatorMsg::new(
+                "ob_v2_sol_usdc_txs".to_string(),
+                1,
+                0,
+                BotMsg::Unimplemented,
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        for msg in samples() {
+            let encoded = serde_json::to_string(&msg).unwrap();
+            let decoded: IndicatorMsg = serde_json::from_str(&encoded).unwrap();
+            assert_eq!(decoded, msg);
+        }
+    }
+
+    #[test]
+    fn test_msgpack_round_trip() {
+        for msg in samples() {
+            let encoded = rmp_serde::to_vec_named(&msg).unwrap();
+            let decoded: IndicatorMsg = rmp_serde::from_slice(&encoded).unwrap();
+            assert_eq!(decoded, msg);
+        }
+    }
+
+    #[test]
+    fn test_json_layout() {
+        let msg = &samples()[0];
+        let value = serde_json::to_value(msg).unwrap();
+
+        assert_eq!(value["version"], SCHEMA_VERSION);
+        assert_eq!(value["data"]["type"], "ob_v2_books");
+        assert_eq!(
+            value["data"]["data"]["books"][0]["order_id"],
+            (u128::MAX - 1).to_string()
+        );
+        // Exact decimals, not floats
+        assert_eq!(
+            value["data"]["data"]["books"][0]["price"],
+            "101.250000000000000000"
+        );
+    }
+
+    #[test]
+    fn test_units() {
+        let msg = &samples()[0];
+        let book = |units| msg.to_value(units).unwrap()["data"]["data"]["books"][0].clone();
+
+        assert_eq!(
+            book(Units::All),
+            serde_json::to_value(&msg.data).unwrap()["data"]["books"][0]
+        );
+        assert_eq!(book(Units::Ui)["price"], "101.250000000000000000");
+        assert!(book(Units::Ui).get("price_lots").is_none());
+        assert_eq!(book(Units::Lots)["price_lots"], 101_250);
+        assert_eq!(book(Units::Lots)["amount_lots"], 500);
+        assert!(book(Units::Lots).get("amount").is_none());
+        assert_eq!(book(Units::Native)["amount_native"], 500_000_000);
+        assert!(book(Units::Native).get("amount_lots").is_none());
+        assert_eq!(book(Units::Ui)["peg_limit"], "102.000000000000000000");
+        assert_eq!(book(Units::Lots)["peg_limit_lots"], 102_000);
+        assert!(book(Units::Lots).get("peg_limit").is_none());
+        assert_eq!(book(Units::Lots)["expiry_timestamp"], 1_700_000_060);
+
+        // Events are nested in their envelope
+        let events = samples()[1].to_value(Units::Native).unwrap();
+        let fill = &events["data"]["data"][0]["event"]["data"];
+        assert_eq!(fill["maker_fee_native"], -200);
+        assert!(fill.get("maker_fee").is_none());
+    }
+}
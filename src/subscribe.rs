@@ -1,48 +1,658 @@
-use super::Extractor;
-use super::Parser;
+use crate::blocks::BlockTimes;
+use crate::commitment::{subscription, Commitment, CommitmentGate};
+use crate::error::{GeyserError, ERRORS_INDICATOR};
+use crate::gap::GapTracker;
+use crate::history::{AccountHistory, ACCOUNT_HISTORY_CAPACITY};
+use crate::registry::PluginRegistry;
+use crate::sinks::{publish, Sink};
 use crate::structs::ParsedBlock;
-use crate::structs::{Account, MessageTransaction};
+use crate::structs::{Account, BotMsg, IndicatorMsg, MessageTransaction};
+use crate::utils::{get_clock, load_clock};
+use crate::Aggregator;
 use futures::{sink::SinkExt, stream::StreamExt};
 use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::sysvar;
 use std::{
     collections::{HashMap, HashSet},
+    fmt,
     time::Duration,
 };
+use tokio::sync::mpsc;
 use tokio::time::{interval, timeout};
 use yellowstone_grpc_client::{GeyserGrpcClient, GeyserGrpcClientError};
 use yellowstone_grpc_proto::geyser::SubscribeRequestFilterBlocksMeta;
-use yellowstone_grpc_proto::geyser::SubscribeRequestPing;
+use yellowstone_grpc_proto::geyser::{
+    SubscribeRequestFilterSlots, SubscribeRequestPing, SubscribeUpdateAccount,
+    SubscribeUpdateBlockMeta, SubscribeUpdateSlot, SubscribeUpdateTransaction,
+};
 // use structs::response_data::IndicatorData;
 use yellowstone_grpc_proto::prelude::{
     subscribe_update::UpdateOneof, CommitmentLevel, SubscribeRequest,
-    SubscribeRequestFilterAccounts, SubscribeRequestFilterTransactions,
+    SubscribeRequestFilterAccounts, SubscribeRequestFilterTransactions, SubscribeUpdate,
 };
 
 type AccountsFilterMap = HashMap<String, SubscribeRequestFilterAccounts>;
 type TransactionsFilterMap = HashMap<String, SubscribeRequestFilterTransactions>;
 
+/// Filter of the `Clock` sysvar, not a plugin
+const CLOCK_FILTER: &str = "clock";
+
+/// Updates buffered between the stream and the plugins, the stream is not read
+/// further while it is full
+const UPDATES_CAPACITY: usize = 10_000;
+
+/// Why an established geyser session ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// Server closed the stream
+    StreamClosed,
+    /// Nothing received for too long
+    Timeout,
+    StreamError(String),
+    PingFailed(String),
+}
+
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisconnectReason::StreamClosed => write!(f, "stream closed by server"),
+            DisconnectReason::Timeout => write!(f, "no message received in time"),
+            DisconnectReason::StreamError(e) => write!(f, "stream error: {}", e),
+            DisconnectReason::PingFailed(e) => write!(f, "ping failed: {}", e),
+        }
+    }
+}
+
 pub fn unique_array(arr: Vec<String>) -> Vec<String> {
     let hashset: HashSet<String> = arr.into_iter().collect();
     hashset.into_iter().collect()
 }
 
+/// Publish plugin output to sinks, followed by the output of aggregators consuming it.
+/// Returns the names of the indicators published.
+pub fn dispatch(
+    aggregators: &mut [Box<dyn Aggregator>],
+    sinks: &mut [Box<dyn Sink>],
+    msg: IndicatorMsg,
+) -> Vec<String> {
+    if let BotMsg::Unimplemented = msg.data {
+        return vec![];
+    }
+
+    let mut derived: Vec<IndicatorMsg> = vec![];
+    for aggregator in aggregators.iter_mut() {
+        if !aggregator.sources().contains(&msg.name) {
+            continue;
+        }
+
+        match aggregator.aggregate(&msg) {
+            Ok(data) => derived.push(IndicatorMsg::new(aggregator.name(), msg.slot, 0, data)),
+            Err(e) => tracing::error!("Aggregator {} error: {}", aggregator.name(), e),
+        }
+    }
+
+    let mut names = vec![msg.name.clone()];
+    publish(sinks, msg);
+    for msg in derived {
+        if let BotMsg::Unimplemented = msg.data {
+            continue;
+        }
+        names.push(msg.name.clone());
+        publish(sinks, msg);
+    }
+    names
+}
+
+/// Update held until its slot reaches the commitment of the plugin behind the filter
+#[derive(Debug)]
+enum HeldUpdate {
+    Account(String, Account),
+    Transaction(String, MessageTransaction),
+}
+
+type Gate = CommitmentGate<HeldUpdate>;
+
+/// Stream state of one geyser session, started over on reconnect
+struct Session {
+    blocks: BlockTimes,
+    gate: Gate,
+    history: AccountHistory,
+    /// (parser, signature) replayed by the backfill, skipped when streamed
+    backfilled: HashSet<(String, String)>,
+}
+
+impl Session {
+    fn new(level: CommitmentLevel) -> Self {
+        // Only processed updates can be rolled back, otherwise the last write orders updates
+        let capacity = if level == CommitmentLevel::Processed {
+            ACCOUNT_HISTORY_CAPACITY
+        } else {
+            1
+        };
+
+        Self {
+            blocks: BlockTimes::default(),
+            gate: Gate::new(level),
+            history: AccountHistory::new(capacity),
+            backfilled: HashSet::new(),
+        }
+    }
+}
+
+/// Run every extractor subscribed through the update's filters, or hold the update
+/// for extractors at a higher commitment than its slot reached.
+/// Writes older than the last one of the account are ignored.
+fn handle_account(
+    registry: &mut PluginRegistry,
+    sinks: &mut [Box<dyn Sink>],
+    tracker: &mut GapTracker,
+    session: &mut Session,
+    filters: Vec<String>,
+    update: SubscribeUpdateAccount,
+) {
+    let mut account = match Account::try_from(update) {
+        Ok(account) => account,
+        Err(e) => {
+            let e = anyhow::Error::from(e);
+            for filter in filters.iter() {
+                registry.errors.record(filter, &e);
+            }
+            return;
+        }
+    };
+    account.block_time = session.blocks.get(account.slot);
+
+    // The sysvar has its own filter, shared by every extractor using cluster time
+    if filters.iter().any(|filter| filter == CLOCK_FILTER) {
+        handle_clock(registry, &account);
+        return;
+    }
+
+    if !session.history.push(&filters, &account) {
+        tracing::debug!(
+            "{}: ignored out of order write {} at slot {}",
+            account.pubkey,
+            account.write_version,
+            account.slot
+        );
+        return;
+    }
+
+    // It can be multi filter
+    for filter in filters {
+        let Some(commitment) = registry.commitment(&filter) else {
+            let e = GeyserError::UnknownFilter(filter.clone()).into();
+            registry.errors.record(&filter, &e);
+            continue;
+        };
+
+        if session.gate.is_ready(account.slot, commitment) {
+            extract_account(
+                registry,
+                sinks,
+                tracker,
+                &mut session.gate,
+                &filter,
+                &mut account,
+            );
+        } else {
+            session.gate.hold(
+                account.slot,
+                commitment,
+                HeldUpdate::Account(filter, account.clone()),
+            );
+        }
+    }
+}
+
+/// Cluster time of a `Clock` sysvar update to the extractors using it
+fn handle_clock(registry: &mut PluginRegistry, account: &Account) {
+    match load_clock(account) {
+        Ok(clock) => {
+            for extractor in registry.extractors.iter_mut().filter(|t| t.uses_clock()) {
+                extractor.update_clock(&clock);
+            }
+        }
+        Err(e) => registry.errors.record(CLOCK_FILTER, &e.into()),
+    }
+}
+
+/// Run the extractor of a filter
+fn extract_account(
+    registry: &mut PluginRegistry,
+    sinks: &mut [Box<dyn Sink>],
+    tracker: &mut GapTracker,
+    gate: &mut Gate,
+    filter: &str,
+    account: &mut Account,
+) {
+    tracker.observe(filter, account.slot, account.write_version);
+
+    let extractor = match registry.extractors.iter_mut().find(|t| t.name().eq(filter)) {
+        Some(extractor) => extractor,
+        None => {
+            let e = GeyserError::UnknownFilter(filter.to_string()).into();
+            registry.errors.record(filter, &e);
+            return;
+        }
+    };
+
+    let result = extractor.extract(account);
+    for e in extractor.take_errors() {
+        registry.errors.record(filter, &e);
+    }
+    match result {
+        Ok(data) => {
+            let commitment = extractor.commitment();
+            let names = dispatch(
+                &mut registry.aggregators,
+                sinks,
+                IndicatorMsg::new(extractor.name(), account.slot, account.write_version, data),
+            );
+            if commitment == Commitment::ProcessedWithUpgrades {
+                for name in names {
+                    gate.track(account.slot, name);
+                }
+            }
+        }
+        Err(e) => registry.errors.record(filter, &e),
+    }
+}
+
+/// Hold a transaction until the block meta of its slot
+fn handle_transaction(
+    registry: &mut PluginRegistry,
+    sinks: &mut [Box<dyn Sink>],
+    tracker: &mut GapTracker,
+    session: &mut Session,
+    filters: Vec<String>,
+    update: SubscribeUpdateTransaction,
+) {
+    let transaction = match MessageTransaction::try_from(update) {
+        Ok(transaction) => transaction,
+        Err(e) => {
+            let e = anyhow::Error::from(e);
+            for filter in filters.iter() {
+                registry.errors.record(filter, &e);
+            }
+            return;
+        }
+    };
+
+    // Already replayed by the backfill of the session
+    let filters = if session.backfilled.is_empty() {
+        filters
+    } else {
+        let signature = transaction.signature.to_string();
+        filters
+            .into_iter()
+            .filter(|filter| {
+                !session
+                    .backfilled
+                    .contains(&(filter.clone(), signature.clone()))
+            })
+            .collect()
+    };
+    if filters.is_empty() {
+        return;
+    }
+
+    if let Some((filters, transaction)) = session.blocks.push(filters, transaction) {
+        parse_transaction(
+            registry,
+            sinks,
+            tracker,
+            &mut session.gate,
+            filters,
+            transaction,
+        );
+    }
+}
+
+/// Release transactions held for this block's time
+fn handle_block_meta(
+    registry: &mut PluginRegistry,
+    sinks: &mut [Box<dyn Sink>],
+    tracker: &mut GapTracker,
+    session: &mut Session,
+    update: SubscribeUpdateBlockMeta,
+) {
+    for (filters, transaction) in session.blocks.on_block(ParsedBlock::from(update)) {
+        parse_transaction(
+            registry,
+            sinks,
+            tracker,
+            &mut session.gate,
+            filters,
+            transaction,
+        );
+    }
+}
+
+/// Run updates released by a slot status, then publish status upgrades of indicators
+/// emitted at processed. Accounts written in rolled back slots are extracted again at
+/// their last write on the surviving branch, so processed books revert the fork.
+fn handle_slot(
+    registry: &mut PluginRegistry,
+    sinks: &mut [Box<dyn Sink>],
+    tracker: &mut GapTracker,
+    session: &mut Session,
+    update: SubscribeUpdateSlot,
+) {
+    let status = match update.status {
+        status if status == CommitmentLevel::Processed as i32 => CommitmentLevel::Processed,
+        status if status == CommitmentLevel::Confirmed as i32 => CommitmentLevel::Confirmed,
+        status if status == CommitmentLevel::Finalized as i32 => CommitmentLevel::Finalized,
+        _ => return,
+    };
+
+    let gate = &mut session.gate;
+    let slot_update = gate.on_slot(update.slot, update.parent, status);
+    for held in slot_update.released {
+        match held {
+            HeldUpdate::Account(filter, mut account) => {
+                extract_account(registry, sinks, tracker, gate, &filter, &mut account);
+            }
+            HeldUpdate::Transaction(filter, transaction) => {
+                run_parser(registry, sinks, tracker, gate, &filter, &transaction);
+            }
+        }
+    }
+
+    for (name, slot, status) in slot_update.notices {
+        publish(
+            sinks,
+            IndicatorMsg::new(name, slot, 0, BotMsg::SlotStatus(status)),
+        );
+    }
+
+    if !slot_update.rolled_back.is_empty() {
+        tracing::warn!("Rolled back slots {:?}", slot_update.rolled_back);
+    }
+    for (filters, mut account) in session.history.roll_back(&slot_update.rolled_back) {
+        // Extractors at a higher commitment never saw the fork
+        for filter in filters {
+            let is_processed = registry
+                .commitment(&filter)
+                .is_some_and(|commitment| commitment.level() == CommitmentLevel::Processed);
+            if is_processed {
+                if let Some(extractor) = registry
+                    .extractors
+                    .iter_mut()
+                    .find(|t| t.name().eq(&filter))
+                {
+                    extractor.roll_back();
+                }
+                extract_account(registry, sinks, tracker, gate, &filter, &mut account);
+            }
+        }
+    }
+}
+
+/// Run every parser subscribed through the update's filters, or hold the transaction
+/// for parsers at a higher commitment than its slot reached
+fn parse_transaction(
+    registry: &mut PluginRegistry,
+    sinks: &mut [Box<dyn Sink>],
+    tracker: &mut GapTracker,
+    gate: &mut Gate,
+    filters: Vec<String>,
+    transaction: MessageTransaction,
+) {
+    for filter in filters {
+        let Some(commitment) = registry.commitment(&filter) else {
+            let e = GeyserError::UnknownFilter(filter.clone()).into();
+            registry.errors.record(&filter, &e);
+            continue;
+        };
+
+        if gate.is_ready(transaction.slot, commitment) {
+            run_parser(registry, sinks, tracker, gate, &filter, &transaction);
+        } else {
+            gate.hold(
+                transaction.slot,
+                commitment,
+                HeldUpdate::Transaction(filter, transaction.clone()),
+            );
+        }
+    }
+}
+
+/// Run the parser of a filter
+fn run_parser(
+    registry: &mut PluginRegistry,
+    sinks: &mut [Box<dyn Sink>],
+    tracker: &mut GapTracker,
+    gate: &mut Gate,
+    filter: &str,
+    transaction: &MessageTransaction,
+) {
+    tracker.observe(filter, transaction.slot, 0);
+
+    let parser = match registry.parsers.iter().find(|t| t.name().eq(filter)) {
+        Some(parser) => parser,
+        None => {
+            let e = GeyserError::UnknownFilter(filter.to_string()).into();
+            registry.errors.record(filter, &e);
+            return;
+        }
+    };
+
+    match parser.parse(transaction) {
+        Ok(data) => {
+            let commitment = parser.commitment();
+            let names = dispatch(
+                &mut registry.aggregators,
+                sinks,
+                IndicatorMsg::new(parser.name(), transaction.slot, 0, data),
+            );
+            if commitment == Commitment::ProcessedWithUpgrades {
+                for name in names {
+                    gate.track(transaction.slot, name);
+                }
+            }
+        }
+        Err(e) => registry.errors.record(filter, &e),
+    }
+}
+
+/// Slot of a streamed update, none for pings
+fn update_slot(update: &SubscribeUpdate) -> Option<u64> {
+    match update.update_oneof.as_ref()? {
+        UpdateOneof::Account(account) => Some(account.slot),
+        UpdateOneof::Transaction(transaction) => Some(transaction.slot),
+        UpdateOneof::BlockMeta(block_meta) => Some(block_meta.slot),
+        UpdateOneof::Slot(slot) => Some(slot.slot),
+        _ => None,
+    }
+}
+
+fn handle_update(
+    registry: &mut PluginRegistry,
+    sinks: &mut [Box<dyn Sink>],
+    tracker: &mut GapTracker,
+    session: &mut Session,
+    update: SubscribeUpdate,
+) {
+    let slot = update_slot(&update);
+    match update.update_oneof {
+        Some(UpdateOneof::Account(account)) => {
+            handle_account(registry, sinks, tracker, session, update.filters, account);
+        }
+        Some(UpdateOneof::Transaction(transaction)) => {
+            handle_transaction(
+                registry,
+                sinks,
+                tracker,
+                session,
+                update.filters,
+                transaction,
+            );
+        }
+        Some(UpdateOneof::BlockMeta(block_meta)) => {
+            handle_block_meta(registry, sinks, tracker, session, block_meta);
+        }
+        Some(UpdateOneof::Slot(slot)) => {
+            handle_slot(registry, sinks, tracker, session, slot);
+        }
+        _ => {}
+    }
+    if let Some(slot) = slot {
+        publish_errors(registry, sinks, slot);
+    }
+}
+
+/// Publish error counts if any changed
+fn publish_errors(registry: &mut PluginRegistry, sinks: &mut [Box<dyn Sink>], slot: u64) {
+    if let Some(errors) = registry.errors.take_changed() {
+        dispatch(
+            &mut registry.aggregators,
+            sinks,
+            IndicatorMsg::new(
+                ERRORS_INDICATOR.to_string(),
+                slot,
+                0,
+                BotMsg::Errors(errors),
+            ),
+        );
+    }
+}
+
+/// Replay what parsers missed from their last seen slot up to the first streamed one,
+/// before any buffered update. Transactions that fail are skipped and counted,
+/// slots the backfill couldn't cover go out as a gap.
+async fn backfill_parsers(
+    registry: &mut PluginRegistry,
+    sinks: &mut [Box<dyn Sink>],
+    tracker: &mut GapTracker,
+    session: &mut Session,
+    client: &RpcClient,
+    backfills: &[(String, u64)],
+    to_slot: u64,
+) {
+    for (name, from_slot) in backfills.iter() {
+        let Some(parser) = registry.parsers.iter().find(|t| t.name().eq(name)) else {
+            continue;
+        };
+        let backfill = match parser.backfill(client, *from_slot, to_slot).await {
+            Ok(backfill) => backfill,
+            Err(e) => {
+                registry.errors.record(name, &e);
+                continue;
+            }
+        };
+
+        if let Some(gap) = backfill.gap {
+            tracing::warn!(
+                "{}: backfill left out slots {} to {}",
+                name,
+                gap.from_slot,
+                gap.to_slot
+            );
+            let slot = gap.to_slot;
+            dispatch(
+                &mut registry.aggregators,
+                sinks,
+                IndicatorMsg::new(name.clone(), slot, 0, BotMsg::Gap(gap)),
+            );
+        }
+        for e in backfill.errors.iter() {
+            registry.errors.record(name, e);
+        }
+        for (slot, signature, data) in backfill.msgs {
+            session.backfilled.insert((name.clone(), signature));
+            dispatch(
+                &mut registry.aggregators,
+                sinks,
+                IndicatorMsg::new(name.clone(), slot, 0, data),
+            );
+        }
+        tracker.resync(&[name.clone()], to_slot);
+    }
+    publish_errors(registry, sinks, to_slot);
+}
+
 pub async fn subscribe_geyser(
     rpc_url: String,
     triton_url: String,
     triton_token: String,
-    extractors: &mut Vec<Box<dyn Extractor>>,
-    parsers: &Vec<Box<dyn Parser>>,
-) -> anyhow::Result<()> {
-    // Load initial state for extractors
+    registry: &mut PluginRegistry,
+    sinks: &mut Vec<Box<dyn Sink>>,
+    tracker: &mut GapTracker,
+) -> anyhow::Result<DisconnectReason> {
     let client = RpcClient::new(rpc_url);
-    for extractor in extractors.iter_mut() {
-        match extractor.load(&client).await {
+    let resumed_slot = client.get_slot().await?;
+
+    // Report what may have been missed since the previous session
+    let names = registry
+        .extractors
+        .iter()
+        .map(|t| t.name())
+        .chain(registry.parsers.iter().map(|t| t.name()))
+        .collect::<Vec<String>>();
+    for name in names.iter() {
+        if let Some(gap) = tracker.gap(name, resumed_slot) {
+            tracing::warn!(
+                "{}: possible gap from slot {} to {}",
+                name,
+                gap.from_slot,
+                gap.to_slot
+            );
+            dispatch(
+                &mut registry.aggregators,
+                sinks,
+                IndicatorMsg::new(name.clone(), resumed_slot, 0, BotMsg::Gap(gap)),
+            );
+        }
+    }
+
+    // Cluster time for order expiry, books wait for the streamed clock if it can't be read
+    let uses_clock = registry.extractors.iter().any(|t| t.uses_clock());
+    if uses_clock {
+        match get_clock(&client).await {
+            Ok(clock) => {
+                for extractor in registry.extractors.iter_mut().filter(|t| t.uses_clock()) {
+                    extractor.update_clock(&clock);
+                }
+            }
+            Err(e) => tracing::warn!("Failed to read the clock sysvar: {}", e),
+        }
+    }
+
+    // Load initial state for extractors, read at or after the resumed slot
+    for extractor in registry.extractors.iter_mut() {
+        let result = extractor.load(&client).await;
+        for e in extractor.take_errors() {
+            registry.errors.record(&extractor.name(), &e);
+        }
+        match result {
             Ok(data) => {
-                // tracing::info!("{:?}", data);
+                dispatch(
+                    &mut registry.aggregators,
+                    sinks,
+                    IndicatorMsg::new(extractor.name(), resumed_slot, 0, data),
+                );
             }
-            Err(_) => {}
+            Err(e) => registry.errors.record(&extractor.name(), &e),
         }
     }
+    publish_errors(registry, sinks, resumed_slot);
+
+    // Parsers are replayed once the stream is up, from their last seen slot to its first one
+    let backfills = registry
+        .parsers
+        .iter()
+        .filter_map(|parser| {
+            let gap = tracker.gap(&parser.name(), resumed_slot)?;
+            Some((parser.name(), gap.from_slot))
+        })
+        .collect::<Vec<(String, u64)>>();
+    let extractor_names = registry
+        .extractors
+        .iter()
+        .map(|t| t.name())
+        .collect::<Vec<String>>();
+    tracker.resync(&extractor_names, resumed_slot);
 
     // Connect geyser client
     let mut geyser_client = GeyserGrpcClient::build_from_shared(triton_url)?
@@ -51,20 +661,38 @@ pub async fn subscribe_geyser(
         .timeout(Duration::from_secs(10))
         .connect()
         .await
-        .map_err(|e| tracing::error!("Geyser error: {:?}", e))
-        .expect("failed to connect geyser");
+        .map_err(|e| anyhow::anyhow!("failed to connect geyser: {:?}", e))?;
     tracing::info!("Connected to geyser...");
 
     // prepare subscribe filter
     let mut request = SubscribeRequest::default();
 
     let mut accounts_filter: AccountsFilterMap = HashMap::new();
-    for extractor in extractors.iter() {
+    for extractor in registry.extractors.iter() {
+        let extra_accounts = extractor.extra_accounts();
+        // Account and owner lists are and-ed, extra accounts (oracles) have their own owners
+        let owner = if extra_accounts.is_empty() {
+            vec![extractor.program_id()]
+        } else {
+            vec![]
+        };
+        let mut account = vec![extractor.account()];
+        account.extend(extra_accounts);
         accounts_filter.insert(
             extractor.name(),
             SubscribeRequestFilterAccounts {
-                account: vec![extractor.account()],
-                owner: vec![extractor.program_id()],
+                account,
+                owner,
+                filters: [].into(),
+            },
+        );
+    }
+    if uses_clock {
+        accounts_filter.insert(
+            CLOCK_FILTER.to_string(),
+            SubscribeRequestFilterAccounts {
+                account: vec![sysvar::clock::ID.to_string()],
+                owner: vec![],
                 filters: [].into(),
             },
         );
@@ -72,8 +700,8 @@ pub async fn subscribe_geyser(
     request.accounts = accounts_filter;
 
     let mut transaction_filter: TransactionsFilterMap = HashMap::new();
-    if parsers.len() > 0 {
-        for parser in parsers.iter() {
+    if registry.parsers.len() > 0 {
+        for parser in registry.parsers.iter() {
             transaction_filter.insert(
                 parser.name(),
                 SubscribeRequestFilterTransactions {
@@ -89,116 +717,97 @@ pub async fn subscribe_geyser(
         request.transactions = transaction_filter;
     }
 
-    // Get only confirmed status
-    request.set_commitment(CommitmentLevel::Confirmed);
+    // Block times for transactions and events
+    request.blocks_meta = HashMap::from([(
+        "blocks_meta".to_string(),
+        SubscribeRequestFilterBlocksMeta::default(),
+    )]);
+
+    // One commitment per subscription: mixed plugin commitments subscribe at processed
+    // and follow slot statuses to emit each plugin's output at its own commitment
+    let (level, with_slots) = subscription(&registry.commitments());
+    if with_slots {
+        request.slots =
+            HashMap::from([("slots".to_string(), SubscribeRequestFilterSlots::default())]);
+    }
+    request.set_commitment(level);
+    tracing::info!("Subscribing at {:?} commitment", level);
 
-    let (mut subscribe_tx, mut stream) = geyser_client.subscribe().await.unwrap();
+    let (mut subscribe_tx, mut stream) = geyser_client.subscribe().await?;
     subscribe_tx
         .send(request.clone())
         .await
-        .map_err(GeyserGrpcClientError::SubscribeSendError)
-        .unwrap();
+        .map_err(GeyserGrpcClientError::SubscribeSendError)?;
+
+    // Updates wait in the channel while the backfill runs
+    let (updates_tx, mut updates) = mpsc::channel(UPDATES_CAPACITY);
+    let forwarder = tokio::spawn(async move {
+        while let Some(update) = stream.next().await {
+            if updates_tx.send(update).await.is_err() {
+                break;
+            }
+        }
+    });
 
-    let _ = futures::try_join!(
-        async move {
+    // Whichever side stops first ends the session
+    let reason = tokio::select! {
+        reason = async move {
             // Setup ping timer for every 10 seconds
             let mut timer = interval(Duration::from_secs(10));
             let mut id = 0;
             loop {
                 timer.tick().await;
                 id += 1;
-                subscribe_tx
+                if let Err(e) = subscribe_tx
                     .send(SubscribeRequest {
                         ping: Some(SubscribeRequestPing { id }),
                         ..Default::default()
                     })
-                    .await?;
+                    .await
+                {
+                    return DisconnectReason::PingFailed(e.to_string());
+                }
             }
-            #[allow(unreachable_code)]
-            Ok::<(), anyhow::Error>(())
-        },
-        async move {
-            loop {
-                let mut is_received = false;
-                match timeout(Duration::from_secs(10), stream.next()).await {
-                    Ok(Some(message)) => {
-                        is_received = true;
-
-                        match message {
-                            Ok(msg) => {
-                                #[allow(clippy::single_match)]
-                                #[allow(clippy::multiple_unsafe_ops_per_block)]
-                                match msg.update_oneof {
-                                    Some(UpdateOneof::Account(account)) => {
-                                        // It can be multi filter
-                                        let mut account: Account = account.into();
-
-                                        for filter in msg.filters {
-                                            match extractors
-                                                .iter_mut()
-                                                .find(|t| t.name().eq(&filter))
-                                            {
-                                                Some(extractor) => {
-                                                    match extractor.extract(&mut account) {
-                                                        Ok(data) => {
-                                                            // tracing::info!("{:?}", data);
-                                                        }
-                                                        Err(e) => {
-                                                            tracing::error!(
-                                                                "Subscribe account error: {}",
-                                                                e
-                                                            )
-                                                        }
-                                                    }
-                                                }
-                                                None => {}
-                                            };
-                                        }
-                                    }
-                                    Some(UpdateOneof::Transaction(transaction)) => {
-                                        let transaction: MessageTransaction = transaction.into();
-
-                                        for filter in msg.filters {
-                                            match parsers
-                                                .iter()
-                                                .find(|t| t.name().eq(&filter))
-                                                .unwrap()
-                                                .parse(&transaction)
-                                            {
-                                                Ok(data) => {
-                                                    // tracing::info!("{:?}", data);
-                                                }
-                                                Err(e) => {
-                                                    tracing::info!("Subscribe error: {}", e)
-                                                }
-                                            }
-                                        }
-                                    }
-                                    _ => {}
-                                }
-                            }
-                            Err(e) => {
-                                // tracing::info!("Subscribe parse error: {:?}", e);
-                            }
+        } => reason,
+        reason = async move {
+            let mut session = Session::new(level);
+
+            // The first streamed slot ends the backfill window
+            let mut buffered: Vec<SubscribeUpdate> = vec![];
+            let to_slot = loop {
+                match timeout(Duration::from_secs(10), updates.recv()).await {
+                    Ok(Some(Ok(update))) => {
+                        let slot = update_slot(&update);
+                        buffered.push(update);
+                        if let Some(slot) = slot {
+                            break slot;
                         }
                     }
-                    Ok(None) => {}
-                    Err(e) => {
-                        tracing::error!("Subscribe geyser error: {:?}", e);
-                    }
+                    Ok(Some(Err(e))) => return DisconnectReason::StreamError(e.to_string()),
+                    Ok(None) => return DisconnectReason::StreamClosed,
+                    Err(_) => return DisconnectReason::Timeout,
                 }
+            };
+            backfill_parsers(registry, sinks, tracker, &mut session, &client, &backfills, to_slot).await;
+            for update in buffered {
+                handle_update(registry, sinks, tracker, &mut session, update);
+            }
 
-                if !is_received {
-                    break;
+            loop {
+                match timeout(Duration::from_secs(10), updates.recv()).await {
+                    Ok(Some(Ok(update))) => {
+                        handle_update(registry, sinks, tracker, &mut session, update);
+                    }
+                    Ok(Some(Err(e))) => return DisconnectReason::StreamError(e.to_string()),
+                    Ok(None) => return DisconnectReason::StreamClosed,
+                    Err(_) => return DisconnectReason::Timeout,
                 }
-
-                tokio::time::sleep(tokio::time::Duration::from_millis(1)).await;
             }
+        } => reason,
+    };
 
-            Ok::<(), anyhow::Error>(())
-        }
-    );
+    forwarder.abort();
 
-    tracing::info!("Subscribe geyser finished");
-    Ok(())
+    tracing::info!("Subscribe geyser finished: {}", reason);
+    Ok(reason)
 }
@@ -0,0 +1,534 @@
+use rand::Rng;
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::subscribe::DisconnectReason;
+
+/// Jittered exponential backoff between geyser reconnects
+#[derive(Debug, Clone, Deserialize)]
+pub struct Backoff {
+    #[serde(default = "default_initial_ms")]
+    pub initial_ms: u64,
+    #[serde(default = "default_max_ms")]
+    pub max_ms: u64,
+    #[serde(default = "default_multiplier")]
+    pub multiplier: f64,
+    /// Random part of every delay, 0.2 spreads it over +-20%
+    #[serde(default = "default_jitter")]
+    pub jitter: f64,
+    /// Consecutive failed attempts before giving up, none retries forever
+    pub max_retries: Option<u32>,
+    /// Sessions shorter than this count as failed attempts, so a server accepting
+    /// and then dropping connections still backs off
+    #[serde(default = "default_min_healthy_ms")]
+    pub min_healthy_ms: u64,
+}
+
+fn default_initial_ms() -> u64 {
+    500
+}
+
+fn default_max_ms() -> u64 {
+    30_000
+}
+
+fn default_multiplier() -> f64 {
+    2.0
+}
+
+fn default_jitter() -> f64 {
+    0.2
+}
+
+fn default_min_healthy_ms() -> u64 {
+    30_000
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial_ms: default_initial_ms(),
+            max_ms: default_max_ms(),
+            multiplier: default_multiplier(),
+            jitter: default_jitter(),
+            max_retries: None,
+            min_healthy_ms: default_min_healthy_ms(),
+        }
+    }
+}
+
+impl Backoff {
+    /// Delay before the given retry (starting at 1), without jitter
+    pub fn base_delay(&self, attempt: u32) -> Duration {
+        let exp = self.multiplier.powi(attempt.saturating_sub(1) as i32);
+        let delay = (self.initial_ms as f64 * exp).min(self.max_ms as f64);
+        Duration::from_millis(delay as u64)
+    }
+
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let base = self.base_delay(attempt).as_millis() as f64;
+        let jitter = if self.jitter > 0.0 {
+            rand::thread_rng().gen_range(-self.jitter..=self.jitter)
+        } else {
+            0.0
+        };
+        Duration::from_millis((base * (1.0 + jitter)).max(0.0) as u64)
+    }
+}
+
+/// Decides when to resubscribe after a geyser session ends.
+/// Plugins, aggregators and sinks live outside of the session, so their state survives reconnects.
+#[derive(Debug, Clone)]
+pub struct Supervisor {
+    pub backoff: Backoff,
+    /// Consecutive failed attempts
+    pub attempt: u32,
+}
+
+impl Supervisor {
+    pub fn new(backoff: Backoff) -> Self {
+        Self {
+            backoff,
+            attempt: 0,
+        }
+    }
+
+    /// Record the result of a session and how long it ran, returning the delay before
+    /// the next one. Fails once the retry budget is exhausted.
+    pub fn next_delay(
+        &mut self,
+        result: anyhow::Result<DisconnectReason>,
+        uptime: Duration,
+    ) -> anyhow::Result<Duration> {
+        let error = match result {
+            Ok(reason) if uptime >= Duration::from_millis(self.backoff.min_healthy_ms) => {
+                // The session was healthy, so start over with the shortest delay
+                tracing::warn!("Geyser disconnected after {:?}: {}", uptime, reason);
+                self.attempt = 0;
+                None
+            }
+            Ok(reason) => Some(anyhow::anyhow!(
+                "session ended after {:?}: {}",
+                uptime,
+                reason
+            )),
+            Err(e) => Some(e),
+        };
+
+        if let Some(e) = error {
+            self.attempt += 1;
+            tracing::error!(
+                "Geyser subscribe failed (attempt {}): {:?}",
+                self.attempt,
+                e
+            );
+
+            if let Some(max_retries) = self.backoff.max_retries {
+                if self.attempt > max_retries {
+                    return Err(e.context(format!(
+                        "geyser reconnect gave up after {} attempts",
+                        max_retries
+                    )));
+                }
+            }
+        }
+
+        let delay = self.backoff.delay(self.attempt.max(1));
+        tracing::info!("Reconnecting geyser in {:?}", delay);
+        Ok(delay)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::commitment::Commitment;
+    use crate::gap::GapTracker;
+    use crate::registry::PluginRegistry;
+    use crate::sinks::Sink;
+    use crate::structs::{Account, BotMsg, IndicatorMsg};
+    use crate::subscribe::subscribe_geyser;
+    use crate::Extractor;
+    use async_trait::async_trait;
+    use futures::stream::{self, Stream};
+    use solana_client::nonblocking::rpc_client::RpcClient;
+    use solana_sdk::pubkey::Pubkey;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tonic::{transport::Server, Request, Response, Status, Streaming};
+    use yellowstone_grpc_proto::geyser::geyser_server::{Geyser, GeyserServer};
+    use yellowstone_grpc_proto::prelude::{
+        subscribe_update::UpdateOneof, GetBlockHeightRequest, GetBlockHeightResponse,
+        GetLatestBlockhashRequest, GetLatestBlockhashResponse, GetSlotRequest, GetSlotResponse,
+        GetVersionRequest, GetVersionResponse, IsBlockhashValidRequest, IsBlockhashValidResponse,
+        PingRequest, PongResponse, SubscribeRequest, SubscribeUpdate, SubscribeUpdateAccount,
+        SubscribeUpdateAccountInfo,
+    };
+
+    const HEALTHY: Duration = Duration::from_secs(60);
+
+    fn backoff(max_retries: Option<u32>) -> Backoff {
+        Backoff {
+            initial_ms: 10,
+            max_ms: 50,
+            multiplier: 2.0,
+            jitter: 0.0,
+            max_retries,
+            min_healthy_ms: 1_000,
+        }
+    }
+
+    #[test]
+    fn test_base_delay() {
+        let backoff = backoff(None);
+
+        assert_eq!(backoff.base_delay(1), Duration::from_millis(10));
+        assert_eq!(backoff.base_delay(2), Duration::from_millis(20));
+        assert_eq!(backoff.base_delay(3), Duration::from_millis(40));
+        assert_eq!(backoff.base_delay(4), Duration::from_millis(50));
+        assert_eq!(backoff.base_delay(100), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_jitter_bounds() {
+        let backoff = Backoff {
+            jitter: 0.5,
+            ..backoff(None)
+        };
+
+        for _ in 0..100 {
+            let delay = backoff.delay(3).as_millis();
+            assert!((20..=60).contains(&delay));
+        }
+    }
+
+    #[test]
+    fn test_retry_budget() {
+        let mut supervisor = Supervisor::new(backoff(Some(2)));
+        let down = || Err(anyhow::anyhow!("down"));
+
+        assert!(supervisor.next_delay(down(), Duration::ZERO).is_ok());
+        assert!(supervisor.next_delay(down(), Duration::ZERO).is_ok());
+        assert!(supervisor.next_delay(down(), Duration::ZERO).is_err());
+    }
+
+    #[test]
+    fn test_disconnect_resets_budget() {
+        let mut supervisor = Supervisor::new(backoff(Some(2)));
+        let down = || Err(anyhow::anyhow!("down"));
+
+        assert!(supervisor.next_delay(down(), Duration::ZERO).is_ok());
+        assert!(supervisor.next_delay(down(), Duration::ZERO).is_ok());
+        assert_eq!(
+            supervisor
+                .next_delay(Ok(DisconnectReason::StreamClosed), HEALTHY)
+                .unwrap(),
+            Duration::from_millis(10)
+        );
+        assert_eq!(supervisor.attempt, 0);
+        assert!(supervisor.next_delay(down(), Duration::ZERO).is_ok());
+        assert!(supervisor.next_delay(down(), Duration::ZERO).is_ok());
+        assert!(supervisor.next_delay(down(), Duration::ZERO).is_err());
+    }
+
+    #[test]
+    fn test_short_sessions_back_off() {
+        let mut supervisor = Supervisor::new(backoff(Some(3)));
+        let closed = || Ok(DisconnectReason::StreamClosed);
+        let short = Duration::from_millis(5);
+
+        assert_eq!(
+            supervisor.next_delay(closed(), short).unwrap(),
+            Duration::from_millis(10)
+        );
+        assert_eq!(
+            supervisor.next_delay(closed(), short).unwrap(),
+            Duration::from_millis(20)
+        );
+        assert_eq!(
+            supervisor.next_delay(closed(), short).unwrap(),
+            Duration::from_millis(40)
+        );
+        let error = supervisor.next_delay(closed(), short).unwrap_err();
+        assert!(error.to_string().contains("gave up after 3 attempts"));
+    }
+
+    /// Geyser server sending one account update per session, then closing the stream
+    #[derive(Clone, Default)]
+    struct MockGeyser {
+        account: Pubkey,
+        requests: Arc<Mutex<Vec<SubscribeRequest>>>,
+    }
+
+    #[tonic::async_trait]
+    impl Geyser for MockGeyser {
+        type SubscribeStream =
+            Pin<Box<dyn Stream<Item = Result<SubscribeUpdate, Status>> + Send + 'static>>;
+
+        // Newer protos add fields to `SubscribeUpdate`
+        #[allow(clippy::needless_update)]
+        async fn subscribe(
+            &self,
+            request: Request<Streaming<SubscribeRequest>>,
+        ) -> Result<Response<Self::SubscribeStream>, Status> {
+            let mut requests = request.into_inner();
+            let first = requests
+                .message()
+                .await?
+                .ok_or_else(|| Status::invalid_argument("no subscribe request"))?;
+            let filters = first.accounts.keys().cloned().collect::<Vec<String>>();
+
+            let session = {
+                let mut seen = self.requests.lock().unwrap();
+                seen.push(first);
+                seen.len() as u64
+            };
+            let update = SubscribeUpdate {
+                filters,
+                update_oneof: Some(UpdateOneof::Account(SubscribeUpdateAccount {
+                    account: Some(SubscribeUpdateAccountInfo {
+                        pubkey: self.account.to_bytes().to_vec(),
+                        owner: Pubkey::default().to_bytes().to_vec(),
+                        write_version: session,
+                        ..Default::default()
+                    }),
+                    slot: 101 + 10 * session,
+                    is_startup: false,
+                })),
+                ..Default::default()
+            };
+            Ok(Response::new(Box::pin(stream::iter(vec![Ok(update)]))))
+        }
+
+        async fn ping(&self, _: Request<PingRequest>) -> Result<Response<PongResponse>, Status> {
+            Err(Status::unimplemented("ping"))
+        }
+
+        async fn get_latest_blockhash(
+            &self,
+            _: Request<GetLatestBlockhashRequest>,
+        ) -> Result<Response<GetLatestBlockhashResponse>, Status> {
+            Err(Status::unimplemented("get_latest_blockhash"))
+        }
+
+        async fn get_block_height(
+            &self,
+            _: Request<GetBlockHeightRequest>,
+        ) -> Result<Response<GetBlockHeightResponse>, Status> {
+            Err(Status::unimplemented("get_block_height"))
+        }
+
+        async fn get_slot(
+            &self,
+            _: Request<GetSlotRequest>,
+        ) -> Result<Response<GetSlotResponse>, Status> {
+            Err(Status::unimplemented("get_slot"))
+        }
+
+        async fn is_blockhash_valid(
+            &self,
+            _: Request<IsBlockhashValidRequest>,
+        ) -> Result<Response<IsBlockhashValidResponse>, Status> {
+            Err(Status::unimplemented("is_blockhash_valid"))
+        }
+
+        async fn get_version(
+            &self,
+            _: Request<GetVersionRequest>,
+        ) -> Result<Response<GetVersionResponse>, Status> {
+            Err(Status::unimplemented("get_version"))
+        }
+    }
+
+    /// Json rpc server answering every call with a slot 10 past the previous one
+    async fn mock_rpc(slot: u64) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let slot = Arc::new(AtomicU64::new(slot));
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(serve_rpc(stream, slot.clone()));
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    async fn serve_rpc(mut stream: TcpStream, slot: Arc<AtomicU64>) {
+        let mut buf: Vec<u8> = vec![];
+        let mut chunk = [0u8; 4096];
+        loop {
+            // Requests are small, one header block and a json body each
+            let Some(head_end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+                match stream.read(&mut chunk).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                }
+                continue;
+            };
+            let head = String::from_utf8_lossy(&buf[..head_end]).to_lowercase();
+            let len = head
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length:"))
+                .and_then(|len| len.trim().parse::<usize>().ok())
+                .unwrap_or_default();
+            while buf.len() < head_end + 4 + len {
+                match stream.read(&mut chunk).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                }
+            }
+            let request: serde_json::Value =
+                serde_json::from_slice(&buf[head_end + 4..head_end + 4 + len]).unwrap();
+            buf.drain(..head_end + 4 + len);
+
+            let slot = slot.fetch_add(10, Ordering::SeqCst) + 10;
+            let body = serde_json::json!({"jsonrpc": "2.0", "id": request["id"], "result": slot})
+                .to_string();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            if stream.write_all(response.as_bytes()).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Records the slots of its account updates, they must survive reconnects
+    struct RecordingExtractor {
+        account: Pubkey,
+        slots: Arc<Mutex<Vec<u64>>>,
+    }
+
+    #[async_trait]
+    impl Extractor for RecordingExtractor {
+        fn name(&self) -> String {
+            "recording".to_string()
+        }
+
+        fn program_id(&self) -> String {
+            Pubkey::default().to_string()
+        }
+
+        fn account(&self) -> String {
+            self.account.to_string()
+        }
+
+        fn commitment(&self) -> Commitment {
+            Commitment::Confirmed
+        }
+
+        fn extract(&mut self, account: &mut Account) -> anyhow::Result<BotMsg> {
+            self.slots.lock().unwrap().push(account.slot);
+            Ok(BotMsg::Unimplemented)
+        }
+
+        async fn load(&mut self, _client: &RpcClient) -> anyhow::Result<BotMsg> {
+            Ok(BotMsg::Unimplemented)
+        }
+    }
+
+    /// Keeps every published message
+    struct RecordingSink {
+        msgs: Arc<Mutex<Vec<IndicatorMsg>>>,
+    }
+
+    impl Sink for RecordingSink {
+        fn name(&self) -> String {
+            "recording".to_string()
+        }
+
+        fn send(&mut self, msg: &IndicatorMsg) -> anyhow::Result<()> {
+            self.msgs.lock().unwrap().push(msg.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_against_mock_server() {
+        let account = Pubkey::new_unique();
+        let geyser = MockGeyser {
+            account,
+            ..Default::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let geyser_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(
+            Server::builder()
+                .add_service(GeyserServer::new(geyser.clone()))
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+        let rpc_url = mock_rpc(100).await;
+
+        let slots = Arc::new(Mutex::new(vec![]));
+        let msgs = Arc::new(Mutex::new(vec![]));
+        let mut registry = PluginRegistry::default();
+        registry.extractors.push(Box::new(RecordingExtractor {
+            account,
+            slots: slots.clone(),
+        }));
+        let mut sinks: Vec<Box<dyn Sink>> = vec![Box::new(RecordingSink { msgs: msgs.clone() })];
+        let mut tracker = GapTracker::default();
+        // Every session is accepted then closed right away, so it is never healthy
+        let mut supervisor = Supervisor::new(backoff(Some(3)));
+
+        let mut delays = vec![];
+        let error = loop {
+            let started = std::time::Instant::now();
+            let result = subscribe_geyser(
+                rpc_url.clone(),
+                geyser_url.clone(),
+                String::new(),
+                &mut registry,
+                &mut sinks,
+                &mut tracker,
+            )
+            .await;
+            assert_eq!(result.as_ref().ok(), Some(&DisconnectReason::StreamClosed));
+
+            match supervisor.next_delay(result, started.elapsed()) {
+                Ok(delay) => {
+                    delays.push(delay);
+                    tokio::time::sleep(delay).await
+                }
+                Err(e) => break e,
+            }
+        };
+
+        assert!(error.to_string().contains("gave up after 3 attempts"));
+        assert_eq!(
+            delays,
+            vec![10, 20, 40]
+                .into_iter()
+                .map(Duration::from_millis)
+                .collect::<Vec<_>>()
+        );
+
+        // Every session resubscribed the extractor's account
+        let requests = geyser.requests.lock().unwrap();
+        assert_eq!(requests.len(), 4);
+        for request in requests.iter() {
+            assert_eq!(requests[0].accounts, request.accounts);
+            assert_eq!(
+                request.accounts["recording"].account,
+                vec![account.to_string()]
+            );
+        }
+
+        // Plugin and tracker state carried over between sessions
+        assert_eq!(*slots.lock().unwrap(), vec![111, 121, 131, 141]);
+        assert_eq!(tracker.last_seen["recording"], (141, 4));
+        let gaps = msgs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|msg| matches!(msg.data, BotMsg::Gap(_)))
+            .count();
+        assert_eq!(gaps, 3);
+    }
+}
@@ -0,0 +1,621 @@
+use std::fmt;
+use std::str::FromStr;
+
+use decimal::*;
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+use crate::structs::ObV2Position;
+
+/// Digits after the point of `Price` and `Quantity`
+pub const SCALE: u8 = 18;
+
+/// Openbook fee rates are scaled by 1e6 (1_000_000 is 100%)
+const FEES_SCALE_FACTOR: u128 = 1_000_000;
+
+/// Units of the values sent to a consumer. Values are emitted in UI units with raw
+/// `{field}_lots` and `{field}_native` siblings, a consumer can keep only one of them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Units {
+    /// Every representation
+    #[default]
+    All,
+    /// Decimal strings, e.g. SOL and USDC
+    Ui,
+    /// Native token units (lamports), prices in lots as they have no native unit
+    Native,
+    /// Lots as in orders and events, amounts only known in native units stay native
+    Lots,
+}
+
+/// Balances of an open orders account as stored on chain (`Position`) and logged
+/// (`OpenOrdersPositionLog`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RawPosition {
+    pub bids_base_lots: i64,
+    pub bids_quote_lots: i64,
+    pub asks_base_lots: i64,
+    pub base_free_native: u64,
+    pub quote_free_native: u64,
+    pub locked_maker_fees: u64,
+    pub referrer_rebates_available: u64,
+}
+
+/// Lot sizes and decimals of a market, on-chain lots and native amounts to UI units
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MarketUnits {
+    pub base_lot_size: u64,
+    pub quote_lot_size: u64,
+    pub base_decimals: u8,
+    pub quote_decimals: u8,
+}
+
+/// UI price, quote tokens per base token.
+/// Exact to 18 digits, serialized as a decimal string (`Display` of the decimal crate).
+#[decimal(18)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Price {
+    pub v: u128,
+}
+
+/// UI amount of a token. Exact to 18 digits, serialized as a decimal string.
+#[decimal(18)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Quantity {
+    pub v: u128,
+}
+
+/// UI quote amount of a fee, negative for maker rebates
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fee {
+    pub is_rebate: bool,
+    pub amount: Quantity,
+}
+
+impl Price {
+    /// Price in quote lots per base lot to quote per base:
+    /// `price_lots * quote_lot_size * 10^base_decimals / (base_lot_size * 10^quote_decimals)`.
+    /// Any pair of decimals, the base may have fewer than the quote.
+    /// Rounded down past 18 digits, negative lots (invalid on chain) are 0.
+    pub fn from_lots(
+        price_lots: i64,
+        base_lot_size: u64,
+        quote_lot_size: u64,
+        base_decimals: u8,
+        quote_decimals: u8,
+    ) -> Self {
+        let price_lots = u64::try_from(price_lots).unwrap_or_default();
+        let exp = SCALE as i32 + base_decimals as i32 - quote_decimals as i32;
+        Self::new(scale(
+            price_lots as u128 * quote_lot_size as u128,
+            base_lot_size,
+            exp,
+        ))
+    }
+
+    /// Lossy, for ratios and logs
+    pub fn to_f64(self) -> f64 {
+        to_f64(self.v)
+    }
+}
+
+impl Quantity {
+    /// Native units of a token with `decimals`, e.g. lamports to SOL.
+    /// Rounded down past 18 digits.
+    pub fn from_native(native: u128, decimals: u8) -> Self {
+        Self::new(scale(native, 1, SCALE as i32 - decimals as i32))
+    }
+
+    /// `lots` of `lot_size` native units, negative lots (invalid on chain) are 0
+    pub fn from_lots(lots: i64, lot_size: u64, decimals: u8) -> Self {
+        let lots = u64::try_from(lots).unwrap_or_default();
+        Self::from_native(lots as u128 * lot_size as u128, decimals)
+    }
+
+    /// Lossy, for ratios and logs
+    pub fn to_f64(self) -> f64 {
+        to_f64(self.v)
+    }
+}
+
+impl Fee {
+    /// Signed native quote units, negative for rebates
+    pub fn from_native(native: i128, decimals: u8) -> Self {
+        Self {
+            is_rebate: native < 0,
+            amount: Quantity::from_native(native.unsigned_abs(), decimals),
+        }
+    }
+}
+
+impl MarketUnits {
+    /// Price in quote lots per base lot to UI
+    pub fn price(&self, price_lots: i64) -> Price {
+        Price::from_lots(
+            price_lots,
+            self.base_lot_size,
+            self.quote_lot_size,
+            self.base_decimals,
+            self.quote_decimals,
+        )
+    }
+
+    /// Base lots to UI
+    pub fn base_lots(&self, lots: i64) -> Quantity {
+        Quantity::from_lots(lots, self.base_lot_size, self.base_decimals)
+    }
+
+    pub fn base_lots_native(&self, lots: i64) -> u64 {
+        lots_native(lots, self.base_lot_size)
+    }
+
+    pub fn quote_lots_native(&self, lots: i64) -> u64 {
+        lots_native(lots, self.quote_lot_size)
+    }
+
+    /// Native base units to UI
+    pub fn base(&self, native: u128) -> Quantity {
+        Quantity::from_native(native, self.base_decimals)
+    }
+
+    /// Native quote units to UI
+    pub fn quote(&self, native: u128) -> Quantity {
+        Quantity::from_native(native, self.quote_decimals)
+    }
+
+    /// Balances in UI units next to their raw values
+    pub fn position(&self, raw: &RawPosition) -> ObV2Position {
+        // Quote locked in bids includes the maker fees reserved for them
+        let quote_locked = (self.quote_lots_native(raw.bids_quote_lots) as u128
+            + raw.locked_maker_fees as u128)
+            .min(u64::MAX as u128) as u64;
+        let base_locked = self.base_lots_native(raw.asks_base_lots);
+        ObV2Position {
+            bids_base: self.base_lots(raw.bids_base_lots),
+            bids_base_lots: raw.bids_base_lots,
+            asks_base: self.base_lots(raw.asks_base_lots),
+            asks_base_lots: raw.asks_base_lots,
+            base_locked: self.base(base_locked as u128),
+            base_locked_native: base_locked,
+            quote_locked: self.quote(quote_locked as u128),
+            quote_locked_native: quote_locked,
+            base_free: self.base(raw.base_free_native as u128),
+            base_free_native: raw.base_free_native,
+            quote_free: self.quote(raw.quote_free_native as u128),
+            quote_free_native: raw.quote_free_native,
+            locked_maker_fees: self.quote(raw.locked_maker_fees as u128),
+            locked_maker_fees_native: raw.locked_maker_fees,
+            referrer_rebates: self.quote(raw.referrer_rebates_available as u128),
+            referrer_rebates_native: raw.referrer_rebates_available,
+        }
+    }
+
+    /// Native quote traded by a fill of `base_lots` at `price_lots`
+    pub fn fill_quote_native(&self, price_lots: i64, base_lots: i64) -> u64 {
+        let quote_lots = price_lots.max(0) as u128 * base_lots.max(0) as u128;
+        u64::try_from(quote_lots.saturating_mul(self.quote_lot_size as u128)).unwrap_or(u64::MAX)
+    }
+}
+
+/// Native fee of a trade of `quote_native` at a market fee rate (scaled by 1e6, negative
+/// for rebates). Rounded like the program: maker fees and rebates down, taker fees up.
+pub fn fee_native(quote_native: u64, fee_rate: i64, is_taker: bool) -> i64 {
+    let product = quote_native as u128 * fee_rate.unsigned_abs() as u128;
+    let fee = if is_taker {
+        product.div_ceil(FEES_SCALE_FACTOR)
+    } else {
+        product / FEES_SCALE_FACTOR
+    };
+    let fee = i64::try_from(fee).unwrap_or(i64::MAX);
+    if fee_rate < 0 {
+        -fee
+    } else {
+        fee
+    }
+}
+
+/// Keep only the representation in `units` of every value with raw siblings,
+/// e.g. `price` or `price_lots`. Values without raw siblings are kept as they are.
+pub fn project_units(value: &mut Value, units: Units) {
+    match value {
+        Value::Array(items) => {
+            for item in items.iter_mut() {
+                project_units(item, units);
+            }
+        }
+        Value::Object(fields) => {
+            if units != Units::All {
+                let names = fields
+                    .keys()
+                    .filter(|name| !name.ends_with("_lots") && !name.ends_with("_native"))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for name in names {
+                    let lots = format!("{}_lots", name);
+                    let native = format!("{}_native", name);
+                    let has_lots = fields.contains_key(&lots);
+                    let has_native = fields.contains_key(&native);
+                    if !has_lots && !has_native {
+                        continue;
+                    }
+
+                    let keep = match units {
+                        Units::Native | Units::Lots if !has_native => &lots,
+                        Units::Native | Units::Lots if !has_lots => &native,
+                        Units::Native => &native,
+                        Units::Lots => &lots,
+                        Units::All | Units::Ui => &name,
+                    };
+                    for field in [&name, &lots, &native] {
+                        if field != keep {
+                            fields.remove(field);
+                        }
+                    }
+                }
+            }
+
+            for field in fields.values_mut() {
+                project_units(field, units);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn lots_native(lots: i64, lot_size: u64) -> u64 {
+    let native = lots.max(0) as u128 * lot_size as u128;
+    u64::try_from(native).unwrap_or(u64::MAX)
+}
+
+fn pow10(exp: u32) -> Option<U256> {
+    U256::from(10u8).checked_pow(U256::from(exp))
+}
+
+/// `value * 10^exp / divisor`, rounded down and saturated. The exponent is negative
+/// when the token has more decimals than `SCALE`, or the quote more than the base.
+fn scale(value: u128, divisor: u64, exp: i32) -> u128 {
+    if value == 0 {
+        return 0;
+    }
+    if divisor == 0 {
+        tracing::warn!("decimal of {} over a zero divisor, saturated", value);
+        return u128::MAX;
+    }
+
+    let value = U256::from(value);
+    if exp >= 0 {
+        // The divisor fits a u64, past U256 the result can't fit a u128
+        match pow10(exp as u32).and_then(|pow| value.checked_mul(pow)) {
+            Some(numerator) => saturate(numerator / U256::from(divisor)),
+            None => {
+                tracing::warn!("decimal of {} * 10^{} overflows, saturated", value, exp);
+                u128::MAX
+            }
+        }
+    } else {
+        // The value fits a u128, past U256 the result is below 1
+        match pow10(exp.unsigned_abs()).and_then(|pow| pow.checked_mul(U256::from(divisor))) {
+            Some(denominator) => saturate(value / denominator),
+            None => 0,
+        }
+    }
+}
+
+fn saturate(value: U256) -> u128 {
+    u128::try_from(value).unwrap_or_else(|_| {
+        tracing::warn!("decimal {} overflows u128, saturated", value);
+        u128::MAX
+    })
+}
+
+fn to_f64(value: u128) -> f64 {
+    value as f64 / 10f64.powi(SCALE as i32)
+}
+
+/// Parse a decimal string with up to `SCALE` digits after the point, scaled by `SCALE`
+fn parse_scaled(s: &str) -> Result<u128, String> {
+    let (integer, fraction) = s.split_once('.').unwrap_or((s, ""));
+    let is_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+    if integer.is_empty() || s.ends_with('.') || !is_digits(integer) || !is_digits(fraction) {
+        return Err(format!("invalid decimal `{}`", s));
+    }
+    if fraction.len() > SCALE as usize {
+        return Err(format!("decimal `{}` has more than {} digits", s, SCALE));
+    }
+
+    let fraction = format!("{:0<width$}", fraction, width = SCALE as usize);
+    integer
+        .parse::<u128>()
+        .ok()
+        .and_then(|integer| integer.checked_mul(10u128.pow(SCALE as u32)))
+        .and_then(|integer| integer.checked_add(fraction.parse().ok()?))
+        .ok_or_else(|| format!("decimal `{}` out of range", s))
+}
+
+impl fmt::Display for Fee {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_rebate && !self.amount.is_zero() {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", self.amount)
+    }
+}
+
+impl FromStr for Price {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_scaled(s).map(Self::new)
+    }
+}
+
+impl FromStr for Quantity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_scaled(s).map(Self::new)
+    }
+}
+
+impl FromStr for Fee {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (is_rebate, amount) = match s.strip_prefix('-') {
+            Some(amount) => (true, amount),
+            None => (false, s),
+        };
+        Ok(Self {
+            is_rebate,
+            amount: amount.parse()?,
+        })
+    }
+}
+
+// Decimal strings on the wire, json numbers would be read back as f64 by most consumers
+macro_rules! serde_as_string {
+    ($($ty:ty),*) => {$(
+        impl Serialize for $ty {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $ty {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                String::deserialize(deserializer)?
+                    .parse()
+                    .map_err(D::Error::custom)
+            }
+        }
+    )*};
+}
+
+serde_as_string!(Price, Quantity, Fee);
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn assert_close(value: f64, reference: f64) {
+        let tolerance = reference.abs() * 1e-12 + 1e-18;
+        assert!(
+            (value - reference).abs() <= tolerance,
+            "{} != {}",
+            value,
+            reference
+        );
+    }
+
+    fn price(s: &str) -> Price {
+        s.parse().unwrap()
+    }
+
+    fn quantity(s: &str) -> Quantity {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_exact_prices() {
+        // SOL/USDC: 9 and 6 decimals, 0.001 SOL base lots, 1 micro USDC quote lots
+        assert_eq!(Price::from_lots(100, 1_000_000, 1, 9, 6), price("0.1"));
+        assert_eq!(
+            Price::from_lots(142_357, 1_000_000, 1, 9, 6),
+            price("142.357")
+        );
+        assert_eq!(Price::from_lots(-1, 1_000_000, 1, 9, 6), Price::new(0));
+
+        // Base with fewer decimals than the quote
+        assert_eq!(Price::from_lots(3, 1, 10, 6, 9), price("0.03"));
+
+        // Not a power of ten lot size, rounded down at 18 digits
+        assert_eq!(
+            Price::from_lots(1, 3, 1, 0, 0),
+            price("0.333333333333333333")
+        );
+    }
+
+    #[test]
+    fn test_decimal_pairs() {
+        // 6 decimals base (USDC) against a 9 decimals quote (SOL)
+        let units = MarketUnits {
+            base_lot_size: 1,
+            quote_lot_size: 1,
+            base_decimals: 6,
+            quote_decimals: 9,
+        };
+        assert_eq!(units.price(7), price("0.007"));
+        assert_eq!(units.base_lots(1_500_000), quantity("1.5"));
+
+        // One base token costs `price` quote tokens, whatever the decimals and lot sizes
+        for base_decimals in 0..=12u8 {
+            for quote_decimals in 0..=12u8 {
+                for base_exp in 0..=base_decimals as u32 {
+                    for quote_lot_size in [1, 7, 10, 1_000] {
+                        let units = MarketUnits {
+                            base_lot_size: 10u64.pow(base_exp),
+                            quote_lot_size,
+                            base_decimals,
+                            quote_decimals,
+                        };
+                        let one_base = 10i64.pow(base_decimals as u32 - base_exp);
+                        assert_eq!(units.base_lots(one_base), Quantity::from_integer(1));
+
+                        for price_lots in [1, 3, 12_345] {
+                            let quote_native = units.fill_quote_native(price_lots, one_base);
+                            assert_eq!(
+                                units.quote(quote_native as u128).get(),
+                                units.price(price_lots).get(),
+                                "{:?} at {}",
+                                units,
+                                price_lots
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        // Extreme decimals round to 0 or saturate, they never panic
+        assert_eq!(Price::from_lots(1, 1, u64::MAX, 0, 255), Price::new(0));
+        assert_eq!(
+            Price::from_lots(1, u64::MAX, 1, 255, 0),
+            Price::max_instance()
+        );
+        assert_eq!(Price::from_lots(1, 0, 1, 9, 6), Price::max_instance());
+        assert_eq!(Quantity::from_native(u128::MAX, 255), Quantity::new(0));
+        assert_eq!(Quantity::from_native(5, 60), Quantity::new(0));
+        for decimals in [0, 18, 19, 38, 39, 77, 78, 255] {
+            for lot_size in [1, u64::MAX] {
+                Price::from_lots(i64::MAX, lot_size, lot_size, decimals, 255 - decimals);
+                Quantity::from_lots(i64::MAX, lot_size, decimals);
+            }
+        }
+    }
+
+    #[test]
+    fn test_exact_quantities() {
+        assert_eq!(Quantity::from_lots(7, 1_000_000, 9), quantity("0.007"));
+        assert_eq!(Quantity::from_native(1, 18), Quantity::new(1));
+        assert_eq!(Quantity::from_native(1, 19), Quantity::new(0));
+        assert_eq!(
+            Quantity::from_lots(i64::MAX, u64::MAX, 0),
+            Quantity::max_instance()
+        );
+
+        // Maker rebates round down, taker fees up
+        assert_eq!(fee_native(1_000_001, -200, false), -200);
+        assert_eq!(fee_native(1_000_001, 400, true), 401);
+        assert_eq!(fee_native(1_000_000, 400, true), 400);
+        assert_eq!(Fee::from_native(-5, 6).to_string(), "-0.000005000000000000");
+    }
+
+    #[test]
+    fn test_project_units() {
+        let msg = serde_json::json!({
+            "levels": [{"price": "0.1", "price_lots": 100, "amount": "0.5",
+                "amount_lots": 500, "amount_native": 500000000}],
+            "fee": "0.01", "fee_native": 10000,
+            "max_base_lots": 500,
+        });
+        let projected = |units| {
+            let mut msg = msg.clone();
+            project_units(&mut msg, units);
+            msg
+        };
+
+        assert_eq!(projected(Units::All), msg);
+        assert_eq!(
+            projected(Units::Ui),
+            serde_json::json!({
+                "levels": [{"price": "0.1", "amount": "0.5"}],
+                "fee": "0.01",
+                "max_base_lots": 500,
+            })
+        );
+        assert_eq!(
+            projected(Units::Native),
+            serde_json::json!({
+                "levels": [{"price_lots": 100, "amount_native": 500000000}],
+                "fee_native": 10000,
+                "max_base_lots": 500,
+            })
+        );
+        assert_eq!(
+            projected(Units::Lots),
+            serde_json::json!({
+                "levels": [{"price_lots": 100, "amount_lots": 500}],
+                "fee_native": 10000,
+                "max_base_lots": 500,
+            })
+        );
+    }
+
+    #[test]
+    fn test_string_round_trip() {
+        assert_eq!(price("142.357").to_string(), "142.357000000000000000");
+        assert_eq!(price("0.10"), price("0.1"));
+        assert_eq!(quantity("100"), Quantity::from_integer(100));
+        for value in [
+            price("0"),
+            price("0.1"),
+            Price::new(1),
+            Price::max_instance(),
+        ] {
+            assert_eq!(value, price(&value.to_string()));
+        }
+        for fee in [Fee::from_native(-5, 6), Fee::from_native(7, 9)] {
+            assert_eq!(fee, fee.to_string().parse().unwrap());
+        }
+
+        for s in ["", ".1", "1.", "-1", "1e5", "0.0000000000000000001"] {
+            assert!(s.parse::<Price>().is_err(), "{}", s);
+        }
+        assert!(format!("{}0", u128::MAX).parse::<Quantity>().is_err());
+    }
+
+    #[test]
+    fn test_prices_against_f64() {
+        // Seeded, a failure reproduces
+        let mut rng = StdRng::seed_from_u64(0x9e3779b97f4a7c15);
+        for _ in 0..10_000 {
+            let price_lots = rng.gen_range(0..1_000_000i64);
+            let base_lot_size = 10u64.pow(rng.gen_range(0..10)) * rng.gen_range(1..=9u64);
+            let quote_lot_size = 10u64.pow(rng.gen_range(0..4)) * rng.gen_range(1..=9u64);
+            let base_decimals = rng.gen_range(0..10u8);
+            let quote_decimals = rng.gen_range(0..10u8);
+
+            let reference = price_lots as f64 * quote_lot_size as f64 / base_lot_size as f64
+                * 10f64.powi(base_decimals as i32 - quote_decimals as i32);
+            let price = Price::from_lots(
+                price_lots,
+                base_lot_size,
+                quote_lot_size,
+                base_decimals,
+                quote_decimals,
+            );
+            assert_close(price.to_f64(), reference);
+            assert_eq!(price, price.to_string().parse().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_quantities_against_f64() {
+        let mut rng = StdRng::seed_from_u64(0x2545f4914f6cdd1d);
+        for _ in 0..10_000 {
+            let lots = rng.gen_range(0..1i64 << 32);
+            let lot_size = 10u64.pow(rng.gen_range(0..10)) * rng.gen_range(1..=9u64);
+            let decimals = rng.gen_range(0..13u8);
+
+            let reference = lots as f64 * lot_size as f64 / 10f64.powi(decimals as i32);
+            let quantity = Quantity::from_lots(lots, lot_size, decimals);
+            assert_close(quantity.to_f64(), reference);
+
+            // Exact: back to the same native amount
+            let native = quantity.get() / 10u128.pow((SCALE - decimals) as u32);
+            assert_eq!(native, lots as u128 * lot_size as u128);
+        }
+    }
+}
@@ -1,13 +1,16 @@
+use crate::error::GeyserError;
+use crate::structs::Account;
 use anchor_lang::AnchorDeserialize;
+use bytemuck::Pod;
+use openbook_v2::accounts_zerocopy::{AccountReader, KeyedAccountReader};
 use openbook_v2::state::Side;
 use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::clock::Clock;
 use solana_sdk::pubkey::Pubkey;
+use solana_sdk::sysvar;
+use std::mem;
 use std::str::FromStr;
 
-pub fn token_decimals(decimals: u8) -> f64 {
-    (10u64.pow(decimals as u32)) as f64
-}
-
 pub fn is_buy(side: Side) -> bool {
     match side {
         Side::Ask => false,
@@ -34,6 +37,10 @@ pub fn validate_ix_args<T>(data: &mut &Vec<u8>, ix_prefix: [u8; 8]) -> Option<T>
 where
     T: AnchorDeserialize,
 {
+    if data.len() < 8 {
+        return None;
+    }
+
     let ix_header = data[..8].to_vec();
     if !ix_header.eq(&ix_prefix) {
         return None;
@@ -48,10 +55,25 @@ where
     Some(data.unwrap())
 }
 
+/// Tag anchor puts before the event discriminator in `emit_cpi!` instruction data
+pub const EVENT_IX_TAG_LE: [u8; 8] = 0x1d9acb512ea545e4u64.to_le_bytes();
+
+/// Event data (discriminator and payload) of an `emit_cpi!` instruction, none for other instructions
+pub fn event_ix_data(data: &[u8]) -> Option<&[u8]> {
+    if data.len() < 16 || data[..8] != EVENT_IX_TAG_LE {
+        return None;
+    }
+    Some(&data[8..])
+}
+
 pub fn validate_ix_events<T>(data: &mut &Vec<u8>, ix_prefix: [u8; 16]) -> Option<T>
 where
     T: AnchorDeserialize,
 {
+    if data.len() < 16 {
+        return None;
+    }
+
     let ix_header = data[..16].to_vec();
     if !ix_header.eq(&ix_prefix) {
         return None;
@@ -66,6 +88,50 @@ where
     Some(data.unwrap())
 }
 
+/// Zero-copy view of an anchor account, skipping the 8 bytes discriminator
+pub fn load_account_data<T: Pod>(account: &Account) -> Result<&T, GeyserError> {
+    let expected = mem::size_of::<T>() + 8;
+    if account.data.len() < expected {
+        return Err(GeyserError::AccountTooSmall {
+            account: account.pubkey.to_string(),
+            len: account.data.len(),
+            expected,
+        });
+    }
+
+    Ok(bytemuck::from_bytes::<T>(&account.data[8..expected]))
+}
+
+// Lets the program read geyser updates, e.g. oracles to price pegged orders
+impl AccountReader for Account {
+    fn owner(&self) -> &Pubkey {
+        &self.owner
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl KeyedAccountReader for Account {
+    fn key(&self) -> &Pubkey {
+        &self.pubkey
+    }
+}
+
+/// `Clock` sysvar of an account update
+pub fn load_clock(account: &Account) -> Result<Clock, GeyserError> {
+    bincode::deserialize::<Clock>(&account.data)
+        .map_err(|e| GeyserError::InvalidClock(e.to_string()))
+}
+
+/// Current `Clock` sysvar through rpc
+pub async fn get_clock(client: &RpcClient) -> anyhow::Result<Clock> {
+    let data = client.get_account_data(&sysvar::clock::ID).await?;
+    Ok(bincode::deserialize::<Clock>(&data)
+        .map_err(|e| GeyserError::InvalidClock(e.to_string()))?)
+}
+
 pub fn load_pubkey(data: [u64; 4]) -> Pubkey {
     let mut owner_bytes: [u8; 32] = [0; 32];
     for i in 0..4 {
@@ -102,3 +168,42 @@ pub fn shortify_address(address: &str) -> String {
 
     format!("{}...{}", prefix, suffix)
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn account(data: Vec<u8>) -> Account {
+        Account {
+            is_startup: false,
+            slot: 250_000_000,
+            pubkey: sysvar::clock::ID,
+            lamports: 0,
+            owner: sysvar::ID,
+            executable: false,
+            rent_epoch: 0,
+            data,
+            write_version: 0,
+            txn_signature: String::new(),
+            block_time: None,
+        }
+    }
+
+    #[test]
+    fn test_load_clock() {
+        let clock = Clock {
+            slot: 250_000_000,
+            epoch_start_timestamp: 1_699_000_000,
+            epoch: 578,
+            leader_schedule_epoch: 579,
+            unix_timestamp: 1_700_000_000,
+        };
+        let data = bincode::serialize(&clock).unwrap();
+        assert_eq!(load_clock(&account(data)).unwrap(), clock);
+
+        assert!(matches!(
+            load_clock(&account(vec![0; 32])),
+            Err(GeyserError::InvalidClock(_))
+        ));
+    }
+}
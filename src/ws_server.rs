@@ -0,0 +1,193 @@
+use crate::structs::IndicatorMsg;
+use crate::units::Units;
+use futures::{sink::SinkExt, stream::StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_tungstenite::tungstenite::Message;
+
+/// Subscribe to every indicator
+pub const ALL_INDICATORS: &str = "*";
+
+/// Pause after a failed accept so a persistent error doesn't spin
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Framing {
+    #[default]
+    Json,
+    Msgpack,
+}
+
+/// Commands sent by clients as json text frames, e.g.
+/// `{"command":"subscribe","indicators":["ob_v2_sol_usdc_bids"],"format":"msgpack","units":"lots"}`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ClientCommand {
+    Subscribe {
+        indicators: Vec<String>,
+        format: Option<Framing>,
+        units: Option<Units>,
+    },
+    Unsubscribe {
+        indicators: Vec<String>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ServerReply {
+    Subscribed {
+        indicators: Vec<String>,
+        format: Framing,
+        units: Units,
+    },
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Debug, Default)]
+struct ClientState {
+    indicators: HashSet<String>,
+    format: Framing,
+    units: Units,
+}
+
+impl ClientState {
+    fn is_subscribed(&self, name: &str) -> bool {
+        self.indicators.contains(ALL_INDICATORS) || self.indicators.contains(name)
+    }
+
+    fn apply(&mut self, command: ClientCommand) -> ServerReply {
+        match command {
+            ClientCommand::Subscribe {
+                indicators,
+                format,
+                units,
+            } => {
+                self.indicators.extend(indicators);
+                if let Some(format) = format {
+                    self.format = format;
+                }
+                if let Some(units) = units {
+                    self.units = units;
+                }
+            }
+            ClientCommand::Unsubscribe { indicators } => {
+                for indicator in indicators.iter() {
+                    self.indicators.remove(indicator);
+                }
+            }
+        }
+
+        ServerReply::Subscribed {
+            indicators: self.indicators.iter().cloned().collect(),
+            format: self.format,
+            units: self.units,
+        }
+    }
+
+    fn encode(&self, msg: &IndicatorMsg) -> anyhow::Result<Message> {
+        if self.units == Units::All {
+            return match self.format {
+                Framing::Json => Ok(Message::Text(serde_json::to_string(msg)?)),
+                Framing::Msgpack => Ok(Message::Binary(rmp_serde::to_vec_named(msg)?)),
+            };
+        }
+
+        let value = msg.to_value(self.units)?;
+        match self.format {
+            Framing::Json => Ok(Message::Text(serde_json::to_string(&value)?)),
+            Framing::Msgpack => Ok(Message::Binary(rmp_serde::to_vec_named(&value)?)),
+        }
+    }
+}
+
+/// Bind the websocket listener, a taken address fails startup instead of the spawned task
+pub async fn bind_ws_server(addr: &str) -> anyhow::Result<TcpListener> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| anyhow::anyhow!("websocket server: failed to bind {}: {}", addr, e))?;
+    tracing::info!("Websocket server listening on {}", addr);
+    Ok(listener)
+}
+
+/// Accept websocket clients and forward every published `IndicatorMsg` they subscribed to
+pub async fn run_ws_server(listener: TcpListener, publisher: broadcast::Sender<IndicatorMsg>) {
+    loop {
+        // Accept errors are per connection (e.g. out of file descriptors), keep serving
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                tracing::error!("Websocket accept error: {:?}", e);
+                tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let receiver = publisher.subscribe();
+
+        tokio::spawn(async move {
+            match handle_client(stream, peer, receiver).await {
+                Ok(()) => tracing::info!("Websocket client {} disconnected", peer),
+                Err(e) => tracing::error!("Websocket client {} error: {:?}", peer, e),
+            }
+        });
+    }
+}
+
+async fn handle_client(
+    stream: TcpStream,
+    peer: SocketAddr,
+    mut receiver: broadcast::Receiver<IndicatorMsg>,
+) -> anyhow::Result<()> {
+    let ws_stream = tokio_tungstenite::accept_async(stream).await?;
+    let (mut ws_tx, mut ws_rx) = ws_stream.split();
+    let mut state = ClientState::default();
+    tracing::info!("Websocket client {} connected", peer);
+
+    loop {
+        tokio::select! {
+            incoming = ws_rx.next() => {
+                let reply = match incoming {
+                    Some(Ok(Message::Text(text))) => {
+                        match serde_json::from_str::<ClientCommand>(&text) {
+                            Ok(command) => state.apply(command),
+                            Err(e) => ServerReply::Error {
+                                message: format!("invalid command: {}", e),
+                            },
+                        }
+                    }
+                    Some(Ok(Message::Ping(data))) => {
+                        ws_tx.send(Message::Pong(data)).await?;
+                        continue;
+                    }
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(e.into()),
+                };
+
+                ws_tx.send(Message::Text(serde_json::to_string(&reply)?)).await?;
+            }
+            published = receiver.recv() => {
+                match published {
+                    Ok(msg) => {
+                        if state.is_subscribed(&msg.name) {
+                            ws_tx.send(state.encode(&msg)?).await?;
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!("Websocket client {} lagged, skipped {} messages", peer, skipped);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        }
+    }
+
+    Ok(())
+}